@@ -1,13 +1,26 @@
 use std::fs;
 
 use generator::config;
-use pdfium_render::prelude::PdfiumError;
+use generator::imposition::{DuplexFlip, Imposition};
+use generator::{sheet, tile, GenerationError};
 use serde::Deserialize;
 
 fn true_func() -> bool {
     true
 }
 
+fn short_edge() -> DuplexFlip {
+    DuplexFlip::ShortEdge
+}
+
+fn one() -> u8 {
+    1
+}
+
+fn four() -> u8 {
+    4
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct FileBookConfig {
     #[serde(rename = "front")]
@@ -15,19 +28,68 @@ pub struct FileBookConfig {
     #[serde(rename = "back")]
     pub back_pages: Vec<config::Page>,
     pub preferred_font: String,
+    // Older settings files only say whether to make a booklet
     #[serde(default = "true_func")]
     pub reorder_pages: bool,
+    pub imposition: Option<Imposition>,
+    #[serde(default = "short_edge")]
+    pub duplex_flip: DuplexFlip,
+    #[serde(default = "four")]
+    pub sheets_per_signature: u8,
+    #[serde(default)]
+    pub per_section_numbering: bool,
+    #[serde(default)]
+    pub pad_to_full_sheets: bool,
+    #[serde(default)]
+    pub song_layout: config::SongLayout,
+    // Songs can still set their own in their tags
+    pub columns: Option<u8>,
+    #[serde(default)]
+    pub typography: config::Typography,
+    pub page_size: Option<tile::PageSize>,
+    pub sheet_size: Option<tile::PaperSize>,
+    pub grid: Option<sheet::GridSize>,
+    #[serde(default = "one")]
+    pub copies_per_sheet: u8,
+    #[serde(default)]
+    pub crop_marks: bool,
+    #[serde(default)]
+    pub bleed_mm: u8,
+    #[serde(default)]
+    pub creep_per_leaf_um: u16,
+    pub pdf_backend: Option<config::PdfBackend>,
+    pub pdfium_path: Option<String>,
 }
 
-fn parse_args() -> config::BookConfig {
+struct Args {
+    book: config::BookConfig,
+    imposition_map: bool,
+    doctor: bool,
+    preview: bool,
+    dpi: f32,
+}
+
+fn parse_args() -> Args {
     let mut args = std::env::args().skip(1);
     let mut config = None;
     let mut songs = Vec::new();
+    let mut imposition_map = false;
+    let mut doctor = false;
+    let mut preview = false;
+    let mut dpi = 150.0;
+    let mut pdfium_path = None;
 
     while let Some(arg) = args.next() {
         match arg.to_lowercase().as_str() {
             "-h" | "--help" => {
                 println!("Usage: skata-songbok settings.toml song1.txt song2.txt ...");
+                println!("       skata-songbok doctor [settings.toml]");
+                println!("       skata-songbok preview settings.toml song1.txt ... [--dpi <dpi>]");
+                println!("  --section <title>  Start a new section before the following songs");
+                println!("  --prefix <prefix>  Number the songs of that section <prefix>-1, ...");
+                println!("  --imposition-map   Write imposition.svg and imposition.json showing");
+                println!("                     where each page goes, instead of output.pdf");
+                println!("  --pdfium <path>    The Pdfium library, or the folder it's in");
                 std::process::exit(0);
             }
             "-v" | "--version" => {
@@ -37,6 +99,36 @@ fn parse_args() -> config::BookConfig {
                 );
                 std::process::exit(0);
             }
+            "--section" => {
+                let title = args
+                    .next()
+                    .expect("Expected a section title after --section");
+                songs.push(config::SongListItem::Section {
+                    section: config::Section {
+                        title,
+                        ..Default::default()
+                    },
+                });
+            }
+            "--imposition-map" => imposition_map = true,
+            "--pdfium" => {
+                pdfium_path = Some(args.next().expect("Expected a path after --pdfium"));
+            }
+            "doctor" => doctor = true,
+            "preview" => preview = true,
+            "--dpi" => {
+                dpi = args
+                    .next()
+                    .and_then(|dpi| dpi.parse().ok())
+                    .expect("Expected a number after --dpi");
+            }
+            "--prefix" => {
+                let prefix = args.next().expect("Expected a prefix after --prefix");
+                match songs.last_mut() {
+                    Some(config::SongListItem::Section { section }) => section.prefix = prefix,
+                    _ => panic!("--prefix must directly follow a --section"),
+                }
+            }
             filename if filename.ends_with(".toml") => {
                 let toml_str =
                     std::fs::read_to_string(&arg).expect("Failed to open .toml configuration file");
@@ -58,29 +150,208 @@ fn parse_args() -> config::BookConfig {
                     .expect(&format!("Invalid song file name: \"{}\"", &arg))
                     .to_string_lossy()
                     .to_string();
-                songs.push(generator::parse_song_body(
-                    title,
-                    &std::fs::read_to_string(&arg)
-                        .expect(&format!("Failed to open song file: \"{}\"", &arg)),
-                ));
+                let song = std::fs::read_to_string(&arg)
+                    .map_err(GenerationError::from)
+                    .and_then(|body| generator::parse_song_body(title, &body));
+                match song {
+                    Ok(song) => songs.push(config::SongListItem::Song(song)),
+                    Err(e) => {
+                        println!("Failed when reading song file {}:\n{}", &arg, e);
+                        std::process::exit(10);
+                    }
+                }
             }
             _ => panic!("Invalid argument: \"{}\"", arg),
         }
     }
 
-    let file_book_config = config.expect("No .toml configuration file provided.");
-    return config::BookConfig {
+    let defaults = config::BookConfig::default();
+    let file_book_config = match config {
+        Some(file_book_config) => file_book_config,
+        // The checks can be run with the default settings
+        None if doctor => {
+            return Args {
+                book: config::BookConfig {
+                    pdfium_path,
+                    ..defaults
+                },
+                imposition_map,
+                doctor,
+                preview,
+                dpi,
+            }
+        }
+        None => panic!("No .toml configuration file provided."),
+    };
+    return Args {
+        book: book_config(file_book_config, songs, pdfium_path),
+        imposition_map,
+        doctor,
+        preview,
+        dpi,
+    };
+}
+
+// The --pdfium flag takes precedence over the path in the settings file
+fn book_config(
+    file_book_config: FileBookConfig,
+    songs: Vec<config::SongListItem>,
+    pdfium_path: Option<String>,
+) -> config::BookConfig {
+    let defaults = config::BookConfig::default();
+    config::BookConfig {
         front_pages: file_book_config.front_pages,
         back_pages: file_book_config.back_pages,
         preferred_font: file_book_config.preferred_font,
-        reorder_pages: file_book_config.reorder_pages,
+        imposition: file_book_config
+            .imposition
+            .unwrap_or(if file_book_config.reorder_pages {
+                Imposition::Booklet
+            } else {
+                Imposition::Sequential
+            }),
+        duplex_flip: file_book_config.duplex_flip,
+        sheets_per_signature: file_book_config.sheets_per_signature,
         songs,
-    };
+        song_numbering: if file_book_config.per_section_numbering {
+            config::SongNumbering::PerSection
+        } else {
+            config::SongNumbering::Continuous
+        },
+        pad_to_full_sheets: file_book_config.pad_to_full_sheets,
+        song_layout: file_book_config.song_layout,
+        columns: file_book_config.columns.unwrap_or(defaults.columns),
+        typography: file_book_config.typography,
+        tiled_page_size: file_book_config
+            .page_size
+            .unwrap_or(defaults.tiled_page_size),
+        sheet_size: file_book_config.sheet_size.unwrap_or(defaults.sheet_size),
+        grid: file_book_config.grid,
+        copies_per_sheet: file_book_config.copies_per_sheet,
+        crop_marks: file_book_config.crop_marks,
+        bleed_mm: file_book_config.bleed_mm,
+        creep_per_leaf_um: file_book_config.creep_per_leaf_um,
+        pdf_backend: file_book_config.pdf_backend.unwrap_or(defaults.pdf_backend),
+        pdfium_path: pdfium_path.or(file_book_config.pdfium_path),
+        ..defaults
+    }
 }
 
-fn main() -> Result<(), PdfiumError> {
-    let config = parse_args();
-    let pdf = generator::generate_book_pdf(&config)?;
+fn generate(args: &Args) -> Result<(), GenerationError> {
+    if args.preview {
+        for preview in generator::preview_sheets(&args.book, args.dpi)? {
+            let file_name = preview.file_name();
+            fs::write(&file_name, preview.png)
+                .unwrap_or_else(|e| panic!("Failed to write {}: {}", file_name, e));
+        }
+        return Ok(());
+    }
+    if args.imposition_map {
+        let map = generator::generate_imposition_map(&args.book)?;
+        fs::write("imposition.svg", map.to_svg()).expect("Failed to write imposition.svg");
+        fs::write("imposition.json", map.to_json()).expect("Failed to write imposition.json");
+        return Ok(());
+    }
+    let pdf = generator::generate_book_pdf(&args.book)?;
     fs::write("output.pdf", pdf).expect("Failed to write output.pdf");
     Ok(())
 }
+
+fn main() {
+    let args = parse_args();
+    if args.doctor {
+        let checks = generator::doctor::run_checks(&args.book);
+        for check in &checks {
+            println!("{}", check);
+        }
+        let failed = checks
+            .iter()
+            .any(|check| check.status == generator::doctor::CheckStatus::Failed);
+        std::process::exit(if failed { 1 } else { 0 });
+    }
+    if let Err(e) = generate(&args) {
+        println!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pdfium_path_from_flag_or_file() {
+        let file_book_config: FileBookConfig = toml::from_str(
+            r#"
+            front = []
+            back = []
+            preferred_font = "Arial"
+            pdfium_path = "/skrar/libpdfium.so"
+            "#,
+        )
+        .unwrap();
+
+        let book = book_config(file_book_config.clone(), Vec::new(), None);
+        assert_eq!(book.pdfium_path.as_deref(), Some("/skrar/libpdfium.so"));
+        let book = book_config(file_book_config, Vec::new(), Some("./pdfium".to_owned()));
+        assert_eq!(book.pdfium_path.as_deref(), Some("./pdfium"));
+    }
+
+    #[test]
+    fn test_song_layout_from_file() {
+        let file_book_config: FileBookConfig = toml::from_str(
+            r#"
+            front = []
+            back = []
+            preferred_font = "Arial"
+
+            [song_layout]
+            keep_title_with_verse = false
+            new_page_per_song = true
+            "#,
+        )
+        .unwrap();
+
+        let layout = book_config(file_book_config, Vec::new(), None).song_layout;
+        assert!(!layout.keep_title_with_verse);
+        assert!(layout.new_page_per_song);
+        // What the file leaves out keeps its default
+        assert!(layout.keep_verses_together);
+    }
+
+    #[test]
+    fn test_fit_to_page_from_file() {
+        let file_book_config: FileBookConfig = toml::from_str(
+            r#"
+            front = []
+            back = []
+            preferred_font = "Arial"
+
+            [song_layout]
+            fit_to_page = true
+            min_font_size = 8
+            "#,
+        )
+        .unwrap();
+
+        let layout = book_config(file_book_config, Vec::new(), None).song_layout;
+        assert!(layout.fit_to_page);
+        assert_eq!(layout.min_font_size, 8);
+    }
+
+    #[test]
+    fn test_columns_from_file() {
+        let settings = |extra: &str| {
+            format!(
+                "front = []\nback = []\npreferred_font = \"Arial\"\n{}",
+                extra
+            )
+        };
+        let columns = |extra: &str| {
+            let file_book_config: FileBookConfig = toml::from_str(&settings(extra)).unwrap();
+            book_config(file_book_config, Vec::new(), None).columns
+        };
+        assert_eq!(columns("columns = 2"), 2);
+        assert_eq!(columns(""), config::BookConfig::default().columns);
+    }
+}
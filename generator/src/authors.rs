@@ -0,0 +1,156 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use crate::config::Song;
+
+// Song tags (lowercase, as parse_song_body stores them) that name a lyricist or a composer
+const LYRICIST_TAGS: &[&str] = &["höfundur", "texti", "ljóð"];
+const COMPOSER_TAGS: &[&str] = &["lag", "tónskáld"];
+
+const ICELANDIC_ALPHABET: &str = "aábcdðeéfghiíjklmnoópqrstuúvwxyýzþæö";
+
+fn collation_key(s: &str) -> Vec<u32> {
+    s.chars()
+        .flat_map(|c| c.to_lowercase())
+        .map(|c| match ICELANDIC_ALPHABET.chars().position(|a| a == c) {
+            Some(pos) => pos as u32 + 1,
+            // Spaces and hyphens go before every letter, so Jón Jónsson comes before Jóna
+            None if c.is_whitespace() || c == '-' => 0,
+            // Sort anything else outside the alphabet after it, by code point
+            None => ICELANDIC_ALPHABET.chars().count() as u32 + 1 + c as u32,
+        })
+        .collect()
+}
+
+pub fn icelandic_cmp(a: &str, b: &str) -> Ordering {
+    collation_key(a).cmp(&collation_key(b))
+}
+
+fn is_icelandic_patronymic(surname: &str) -> bool {
+    let surname = surname.to_lowercase();
+    if surname.ends_with("dóttir") || surname.ends_with("bur") {
+        return true;
+    }
+
+    // Patronyms are formed from the genitive of the father's name, which ends in -s, -a or -u,
+    // so Jónsson, Helgason and Sturluson, but not Johnson, Harrison, Jameson or Mason.
+    match surname.strip_suffix("son") {
+        Some(stem) => stem.chars().count() >= 3 && stem.ends_with(['s', 'a', 'u']),
+        None => false,
+    }
+}
+
+// Icelanders are sorted by their given name, everyone else by their surname
+fn name_sort_key(name: &str) -> String {
+    let words = name.split_whitespace().collect::<Vec<_>>();
+    match words.split_last() {
+        Some((surname, given)) if !given.is_empty() && !is_icelandic_patronymic(surname) => {
+            format!("{} {}", surname, given.join(" "))
+        }
+        _ => words.join(" "),
+    }
+}
+
+pub fn author_index(songs: &[(String, &Song)]) -> Vec<(String, Vec<String>)> {
+    let mut authors: HashMap<&str, Vec<String>> = HashMap::new();
+    for (num, song) in songs {
+        let names = LYRICIST_TAGS
+            .iter()
+            .chain(COMPOSER_TAGS)
+            .filter_map(|tag| song.tags.get(*tag))
+            .flatten()
+            .map(|name| name.trim())
+            .filter(|name| !name.is_empty());
+        for name in names {
+            let numbers = authors.entry(name).or_default();
+            if !numbers.contains(num) {
+                numbers.push(num.clone());
+            }
+        }
+    }
+
+    let mut authors = authors
+        .into_iter()
+        .map(|(name, numbers)| (name.to_owned(), numbers))
+        .collect::<Vec<_>>();
+    authors.sort_by(|a, b| {
+        icelandic_cmp(&name_sort_key(&a.0), &name_sort_key(&b.0)).then_with(|| a.0.cmp(&b.0))
+    });
+    authors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn song(tags: &[(&str, &[&str])]) -> Song {
+        Song {
+            title: "".to_owned(),
+            body: vec![],
+            tags: tags
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.iter().map(|s| s.to_string()).collect()))
+                .collect(),
+            columns: None,
+        }
+    }
+
+    #[test]
+    fn test_icelandic_cmp() {
+        let mut words = vec!["Þór", "Ösp", "Örn", "Árni", "Óli", "Ari", "Zoe", "Ýr"];
+        words.sort_by(|a, b| icelandic_cmp(a, b));
+        assert_eq!(
+            words,
+            ["Ari", "Árni", "Óli", "Ýr", "Zoe", "Þór", "Örn", "Ösp"]
+        );
+    }
+
+    #[test]
+    fn test_separators_sort_first() {
+        let mut names = vec!["Jóna", "Jón Jónsson", "Jón-Már"];
+        names.sort_by(|a, b| icelandic_cmp(a, b));
+        assert_eq!(names, ["Jón Jónsson", "Jón-Már", "Jóna"]);
+    }
+
+    #[test]
+    fn test_patronymics() {
+        for surname in ["Jónsson", "Helgason", "Sturluson", "Helgadóttir"] {
+            assert!(is_icelandic_patronymic(surname), "{}", surname);
+        }
+        for surname in ["Johnson", "Harrison", "Jameson", "Mason", "Jackson"] {
+            assert!(!is_icelandic_patronymic(surname), "{}", surname);
+        }
+        assert_eq!(name_sort_key("George Harrison"), "Harrison George");
+    }
+
+    #[test]
+    fn test_author_index() {
+        let songs = [
+            song(&[("höfundur", &["Jón Jónsson"]), ("lag", &["John Lennon"])]),
+            song(&[("texti", &["Ásta Helgadóttir", "Jón Jónsson"])]),
+            song(&[("lag", &["Paul McCartney"]), ("texti", &["John Lennon"])]),
+            song(&[("ártal", &["1976"])]),
+        ];
+        let numbered = ["1", "J-1", "J-2", "J-3"]
+            .into_iter()
+            .map(|n| n.to_owned())
+            .zip(songs.iter())
+            .collect::<Vec<_>>();
+        let index = author_index(&numbered);
+        assert_eq!(
+            index,
+            [
+                ("Ásta Helgadóttir", vec!["J-1"]),
+                ("Jón Jónsson", vec!["1", "J-1"]),
+                ("John Lennon", vec!["1", "J-2"]),
+                ("Paul McCartney", vec!["J-2"]),
+            ]
+            .into_iter()
+            .map(|(n, v)| (
+                n.to_owned(),
+                v.into_iter().map(|s| s.to_owned()).collect::<Vec<_>>()
+            ))
+            .collect::<Vec<_>>()
+        );
+    }
+}
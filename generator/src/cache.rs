@@ -0,0 +1,225 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+use std::{fs, io};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::tile::PdfPart;
+
+const INFO_FILE: &str = "info.json";
+
+// Rendered parts of the book, kept on disk between runs so only what has changed is laid out
+// again. Each entry is a folder named after its key, with a PDF for each part in order and what
+// else is known about them.
+#[derive(Debug)]
+pub struct RenderCache {
+    dir: PathBuf,
+    used: Mutex<HashSet<u64>>,
+}
+
+// A key for everything a part is rendered from, so a change to any of it renders it again
+pub fn cache_key(input: &impl Serialize) -> u64 {
+    // Going through a value sorts the maps, e.g. the song tags, so the key is the same each run
+    let json = serde_json::to_value(input)
+        .map(|value| value.to_string())
+        .unwrap_or_default();
+    let mut hasher = DefaultHasher::new();
+    // The way parts are rendered may change between versions
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    json.hash(&mut hasher);
+    hasher.finish()
+}
+
+// When the file was last changed, for parts that show a file such as an image
+pub fn file_stamp(path: &str) -> Option<(u64, u32)> {
+    let modified = fs::metadata(path).and_then(|m| m.modified()).ok()?;
+    let since_epoch = modified.duration_since(UNIX_EPOCH).ok()?;
+    Some((since_epoch.as_secs(), since_epoch.subsec_nanos()))
+}
+
+fn write_parts(dir: &Path, parts: &[PdfPart], info: &impl Serialize) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    fs::write(dir.join(INFO_FILE), serde_json::to_vec(info)?)?;
+    for (i, part) in parts.iter().enumerate() {
+        let (bytes, suffix) = match part {
+            PdfPart::Generated(bytes) => (bytes, ""),
+            PdfPart::Spread(bytes) => (bytes, "-spread"),
+            PdfPart::File { .. } => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Only rendered parts can be cached",
+                ))
+            }
+        };
+        fs::write(dir.join(format!("{:04}{}.pdf", i, suffix)), bytes)?;
+    }
+    Ok(())
+}
+
+impl RenderCache {
+    pub fn new(dir: impl Into<PathBuf>) -> RenderCache {
+        RenderCache {
+            dir: dir.into(),
+            used: Mutex::new(HashSet::new()),
+        }
+    }
+
+    fn entry_dir(&self, key: u64) -> PathBuf {
+        self.dir.join(format!("{:016x}", key))
+    }
+
+    fn mark_used(&self, key: u64) {
+        if let Ok(mut used) = self.used.lock() {
+            used.insert(key);
+        }
+    }
+
+    // Anything that can't be read is rendered again, so errors are treated as a miss
+    pub fn get<T: DeserializeOwned>(&self, key: u64) -> Option<(Vec<PdfPart>, T)> {
+        let entry_dir = self.entry_dir(key);
+        let info = serde_json::from_slice(&fs::read(entry_dir.join(INFO_FILE)).ok()?).ok()?;
+        let mut files = fs::read_dir(&entry_dir)
+            .ok()?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .filter(|path| {
+                path.as_ref().map_or(true, |path| {
+                    path.extension().is_some_and(|ext| ext == "pdf")
+                })
+            })
+            .collect::<io::Result<Vec<_>>>()
+            .ok()?;
+        files.sort();
+        let parts = files
+            .iter()
+            .map(|path| {
+                let bytes = fs::read(path)?;
+                let is_spread = path
+                    .file_stem()
+                    .is_some_and(|stem| stem.to_string_lossy().ends_with("-spread"));
+                Ok(if is_spread {
+                    PdfPart::Spread(bytes)
+                } else {
+                    PdfPart::Generated(bytes)
+                })
+            })
+            .collect::<io::Result<Vec<_>>>()
+            .ok()?;
+        self.mark_used(key);
+        Some((parts, info))
+    }
+
+    // The info is whatever else was found out while laying out the parts
+    pub fn insert(&self, key: u64, parts: &[PdfPart], info: &impl Serialize) -> io::Result<()> {
+        // Written next to the entry and then moved in place, so a half written entry is never
+        // read. Parts that are the same, such as blank pages, have the same key and may be
+        // written at the same time, so each writer has its own folder.
+        static WRITERS: AtomicUsize = AtomicUsize::new(0);
+        let entry_dir = self.entry_dir(key);
+        let temp_dir = entry_dir.with_extension(format!(
+            "{}-{}.tmp",
+            std::process::id(),
+            WRITERS.fetch_add(1, Ordering::Relaxed)
+        ));
+        let result = write_parts(&temp_dir, parts, info).and_then(|()| {
+            match fs::rename(&temp_dir, &entry_dir) {
+                // Someone else got there first with the same parts
+                Err(_) if entry_dir.is_dir() => Ok(()),
+                result => result,
+            }
+        });
+        let _ = fs::remove_dir_all(&temp_dir);
+        result?;
+        self.mark_used(key);
+        Ok(())
+    }
+
+    // Removes the entries that weren't used since the cache was opened, so it only holds the
+    // parts of the last book made
+    pub fn prune(&self) -> io::Result<()> {
+        let used = match self.used.lock() {
+            Ok(used) => used,
+            Err(_) => return Ok(()),
+        };
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            // Nothing has been cached yet
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        for entry in entries {
+            let path = entry?.path();
+            let is_used = path
+                .file_name()
+                .and_then(|name| u64::from_str_radix(&name.to_string_lossy(), 16).ok())
+                .is_some_and(|key| used.contains(&key));
+            if path.is_dir() && !is_used {
+                fs::remove_dir_all(&path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_cache_round_trip() {
+        let dir = std::env::temp_dir().join("skata-songbok-cache-test");
+        let _ = fs::remove_dir_all(&dir);
+        let parts = vec![
+            PdfPart::Generated(b"fyrsta".to_vec()),
+            PdfPart::Spread(b"annad".to_vec()),
+        ];
+
+        let cache = RenderCache::new(&dir);
+        assert_eq!(cache.get::<()>(1), None);
+        cache.insert(1, &parts, &vec!["Lag"]).unwrap();
+        cache.insert(2, &parts[..1], &()).unwrap();
+        assert_eq!(cache.get(1), Some((parts.clone(), vec!["Lag".to_owned()])));
+
+        // Only the entries used by the next run are kept
+        let cache = RenderCache::new(&dir);
+        assert!(cache.get::<()>(2).is_some());
+        cache.prune().unwrap();
+        assert_eq!(cache.get::<()>(1), None);
+        assert!(cache.get::<()>(2).is_some());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_cache_same_key_at_once() {
+        let dir = std::env::temp_dir().join("skata-songbok-cache-threads-test");
+        let _ = fs::remove_dir_all(&dir);
+        let cache = RenderCache::new(&dir);
+        let parts = vec![PdfPart::Generated(vec![7; 100_000])];
+
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| cache.insert(3, &parts, &()).unwrap());
+            }
+        });
+        assert_eq!(cache.get(3), Some((parts, ())));
+        // Only the entry is left behind
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_cache_key_ignores_map_order() {
+        let a: HashMap<_, _> = (0..20).map(|i| (i.to_string(), i)).collect();
+        let b: HashMap<_, _> = (0..20).rev().map(|i| (i.to_string(), i)).collect();
+        assert_eq!(cache_key(&a), cache_key(&b));
+        assert_ne!(cache_key(&a), cache_key(&(&a, 1)));
+    }
+}
@@ -2,12 +2,15 @@ use std::collections::HashMap;
 
 use serde::{Deserialize, Serialize};
 
-use crate::tile;
+use crate::imposition::{DuplexFlip, Imposition};
+use crate::{sheet, tile, GenerationError};
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Preface {
     pub title: String,
     pub body: String,
+    #[serde(default)]
+    pub markdown: bool,
 }
 
 impl Default for Preface {
@@ -15,10 +18,17 @@ impl Default for Preface {
         Preface {
             title: "Formáli".to_owned(),
             body: "".to_owned(),
+            markdown: false,
         }
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct CustomPage {
+    pub title: String,
+    pub body: String,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TableOfContentsSortOrder {
     Alphabetical,
@@ -35,10 +45,16 @@ impl std::fmt::Display for TableOfContentsSortOrder {
     }
 }
 
+fn default_columns() -> u8 {
+    1
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TableOfContents {
     pub title: String,
     pub order: TableOfContentsSortOrder,
+    #[serde(default = "default_columns")]
+    pub columns: u8,
 }
 
 impl Default for TableOfContents {
@@ -46,6 +62,66 @@ impl Default for TableOfContents {
         TableOfContents {
             title: "Efnisyfirlit".to_owned(),
             order: TableOfContentsSortOrder::SongNumber,
+            columns: default_columns(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuthorIndex {
+    pub title: String,
+}
+
+impl Default for AuthorIndex {
+    fn default() -> Self {
+        AuthorIndex {
+            title: "Höfundaskrá".to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ImageAlignment {
+    Left,
+    Center,
+    Right,
+}
+
+impl std::fmt::Display for ImageAlignment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Left => "Vinstri",
+            Self::Center => "Miðja",
+            Self::Right => "Hægri",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+fn default_image_width() -> u8 {
+    50
+}
+
+fn default_image_alignment() -> ImageAlignment {
+    ImageAlignment::Center
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImageConfig {
+    pub path: String,
+    // Percentage of the text width
+    #[serde(default = "default_image_width")]
+    pub width: u8,
+    #[serde(default = "default_image_alignment")]
+    pub alignment: ImageAlignment,
+}
+
+impl Default for ImageConfig {
+    fn default() -> Self {
+        ImageConfig {
+            path: "".to_owned(),
+            width: default_image_width(),
+            alignment: default_image_alignment(),
         }
     }
 }
@@ -54,6 +130,8 @@ impl Default for TableOfContents {
 pub struct FrontPage {
     pub title: String,
     pub version: String,
+    #[serde(default)]
+    pub images: Vec<ImageConfig>,
 }
 
 impl Default for FrontPage {
@@ -61,6 +139,62 @@ impl Default for FrontPage {
         FrontPage {
             title: "Þín Skáta Söngbók".to_owned(),
             version: env!("CARGO_PKG_VERSION").to_owned(),
+            images: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct ExternalPdf {
+    pub path: String,
+    // One-based pages and page ranges, e.g. "1-3, 5". Empty for every page.
+    #[serde(default)]
+    pub pages: String,
+}
+
+impl ExternalPdf {
+    // Zero-based indices of the selected pages, or None if every page should be used
+    pub fn page_indices(&self) -> Result<Option<Vec<u16>>, String> {
+        if self.pages.trim().is_empty() {
+            return Ok(None);
+        }
+
+        let parse_page = |s: &str| match s.trim().parse::<u16>() {
+            Ok(page) if page > 0 => Ok(page - 1),
+            _ => Err(format!("Invalid page \"{}\" in {}", s.trim(), self.path)),
+        };
+        let mut indices = Vec::new();
+        for range in self.pages.split(',') {
+            match range.split_once('-') {
+                Some((start, end)) => {
+                    let (start, end) = (parse_page(start)?, parse_page(end)?);
+                    if start > end {
+                        return Err(format!(
+                            "Invalid page range \"{}\" in {}, the first page is after the last",
+                            range.trim(),
+                            self.path
+                        ));
+                    }
+                    indices.extend(start..=end);
+                }
+                None => indices.push(parse_page(range)?),
+            }
+        }
+        Ok(Some(indices))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Notes {
+    pub title: String,
+    pub lines: u16,
+}
+
+impl Default for Notes {
+    fn default() -> Self {
+        Notes {
+            title: "Glósur".to_owned(),
+            lines: 20,
         }
     }
 }
@@ -70,6 +204,11 @@ pub enum Page {
     Preface(Preface),
     TableOfContents(TableOfContents),
     FrontPage(FrontPage),
+    AuthorIndex(AuthorIndex),
+    Custom(CustomPage),
+    ExternalPdf(ExternalPdf),
+    Blank,
+    Notes(Notes),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -79,6 +218,9 @@ pub struct Song {
     pub body: Vec<String>,
     #[serde(skip)]
     pub tags: HashMap<String, Vec<String>>,
+    // Overrides the number of columns set for the whole book
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub columns: Option<u8>,
 }
 
 impl std::fmt::Display for Song {
@@ -87,8 +229,171 @@ impl std::fmt::Display for Song {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SectionStyle {
+    Heading,
+    FullPage,
+}
+
+impl std::fmt::Display for SectionStyle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Heading => "Fyrirsögn",
+            Self::FullPage => "Heil síða",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+fn default_section_style() -> SectionStyle {
+    SectionStyle::Heading
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Section {
+    pub title: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub prefix: String,
+    #[serde(default = "default_section_style")]
+    pub style: SectionStyle,
+}
+
+impl Default for Section {
+    fn default() -> Self {
+        Section {
+            title: "Kafli".to_owned(),
+            description: "".to_owned(),
+            prefix: "".to_owned(),
+            style: default_section_style(),
+        }
+    }
+}
+
+// Untagged so song lists saved before sections existed still load
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum SongListItem {
+    Section { section: Section },
+    Song(Song),
+}
+
+impl std::fmt::Display for SongListItem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Section { section } => write!(f, "{}", section.title),
+            Self::Song(song) => write!(f, "{}", song),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SongNumbering {
+    Continuous,
+    PerSection,
+}
+
+impl std::fmt::Display for SongNumbering {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Continuous => "Samfelld",
+            Self::PerSection => "Eftir köflum",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+fn default_song_numbering() -> SongNumbering {
+    SongNumbering::Continuous
+}
+
+// What merges the pages and lays them out on the sheets
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PdfBackend {
+    // lopdf, which is built in
+    Native,
+    // The Pdfium library, which has to be installed next to the program
+    Pdfium,
+}
+
+impl std::fmt::Display for PdfBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Native => "Innbyggt",
+            Self::Pdfium => "Pdfium",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+fn default_pdf_backend() -> PdfBackend {
+    PdfBackend::Native
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SongLayout {
+    pub keep_verses_together: bool,
+    pub keep_title_with_verse: bool,
+    pub new_page_per_song: bool,
+    // Songs longer than a page start on an even page so they can be read across a spread
+    pub long_songs_on_even_page: bool,
+    // Put each song on a single page, shrinking the font down to min_font_size if needed
+    pub fit_to_page: bool,
+    pub min_font_size: u8,
+}
+
+impl Default for SongLayout {
+    fn default() -> Self {
+        SongLayout {
+            keep_verses_together: true,
+            keep_title_with_verse: true,
+            new_page_per_song: false,
+            long_songs_on_even_page: false,
+            fit_to_page: false,
+            min_font_size: 6,
+        }
+    }
+}
+
+// Font sizes in points and margins in millimeters, as they're printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Typography {
+    pub title_font_size: u8,
+    pub song_title_font_size: u8,
+    pub body_font_size: u8,
+    pub margins_mm: u8,
+}
+
+impl Default for Typography {
+    fn default() -> Self {
+        Typography {
+            title_font_size: 16,
+            song_title_font_size: 12,
+            body_font_size: 10,
+            margins_mm: 5,
+        }
+    }
+}
+
+// The pages already have their own margins, so they're tiled at full size unless the printer
+// needs more room
 fn default_padding() -> u8 {
-    10
+    0
+}
+
+fn default_imposition() -> Imposition {
+    Imposition::Booklet
+}
+
+fn default_duplex_flip() -> DuplexFlip {
+    DuplexFlip::ShortEdge
+}
+
+fn default_sheets_per_signature() -> u8 {
+    4
 }
 
 fn default_add_separator() -> bool {
@@ -99,19 +404,63 @@ fn default_tiled_page_size() -> tile::PageSize {
     tile::PageSize::A7
 }
 
+fn default_copies_per_sheet() -> u8 {
+    1
+}
+
+fn default_sheet_size() -> tile::PaperSize {
+    tile::PaperSize::A4
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct BookConfig {
     pub front_pages: Vec<Page>,
     pub back_pages: Vec<Page>,
-    pub songs: Vec<Song>,
+    pub songs: Vec<SongListItem>,
+    #[serde(default = "default_song_numbering")]
+    pub song_numbering: SongNumbering,
+    #[serde(default)]
+    pub song_layout: SongLayout,
+    #[serde(default = "default_columns")]
+    pub columns: u8,
+    #[serde(default)]
+    pub typography: Typography,
     pub preferred_font: String,
-    pub reorder_pages: bool,
+    #[serde(default = "default_imposition")]
+    pub imposition: Imposition,
+    #[serde(default = "default_duplex_flip")]
+    pub duplex_flip: DuplexFlip,
+    #[serde(default = "default_sheets_per_signature")]
+    pub sheets_per_signature: u8,
     #[serde(default = "default_padding")]
     pub padding: u8,
     #[serde(default = "default_add_separator")]
     pub add_separator: bool,
+    #[serde(default)]
+    pub crop_marks: bool,
+    #[serde(default)]
+    pub bleed_mm: u8,
+    // Pages of folded booklets are moved toward the spine by this much for each leaf folded
+    // around them, usually about the thickness of the paper
+    #[serde(default)]
+    pub creep_per_leaf_um: u16,
     #[serde(default = "default_tiled_page_size")]
     pub tiled_page_size: tile::PageSize,
+    #[serde(default = "default_sheet_size")]
+    pub sheet_size: tile::PaperSize,
+    // Rows and columns of pages on each side of the sheet, or as many as fit if None
+    #[serde(default)]
+    pub grid: Option<sheet::GridSize>,
+    // Copies of the whole book printed side by side on each sheet
+    #[serde(default = "default_copies_per_sheet")]
+    pub copies_per_sheet: u8,
+    #[serde(default)]
+    pub pad_to_full_sheets: bool,
+    #[serde(default = "default_pdf_backend")]
+    pub pdf_backend: PdfBackend,
+    // The Pdfium library or the folder it's in, if it's not next to the program
+    #[serde(default)]
+    pub pdfium_path: Option<String>,
 }
 
 impl Default for BookConfig {
@@ -120,11 +469,235 @@ impl Default for BookConfig {
             front_pages: vec![],
             back_pages: vec![],
             songs: vec![],
+            song_numbering: default_song_numbering(),
+            song_layout: SongLayout::default(),
+            columns: default_columns(),
+            typography: Typography::default(),
             preferred_font: "Arial".to_owned(),
-            reorder_pages: true,
+            imposition: default_imposition(),
+            duplex_flip: default_duplex_flip(),
+            sheets_per_signature: default_sheets_per_signature(),
             padding: default_padding(),
             add_separator: default_add_separator(),
+            crop_marks: false,
+            bleed_mm: 0,
+            creep_per_leaf_um: 0,
             tiled_page_size: default_tiled_page_size(),
+            sheet_size: default_sheet_size(),
+            grid: None,
+            copies_per_sheet: default_copies_per_sheet(),
+            pad_to_full_sheets: false,
+            pdf_backend: default_pdf_backend(),
+            pdfium_path: None,
+        }
+    }
+}
+
+impl BookConfig {
+    // Settings saved by older versions are moved over to the settings that replaced them
+    pub fn from_json(json: &str) -> Result<BookConfig, serde_json::Error> {
+        let mut value: serde_json::Value = serde_json::from_str(json)?;
+        if let Some(settings) = value.as_object_mut() {
+            // Before there were other impositions the pages were either reordered into a booklet
+            // or left in order
+            if let Some(reorder_pages) = settings.remove("reorder_pages") {
+                let imposition = match reorder_pages.as_bool() {
+                    Some(false) => Imposition::Sequential,
+                    _ => Imposition::Booklet,
+                };
+                if !settings.contains_key("imposition") {
+                    settings.insert("imposition".to_owned(), serde_json::to_value(imposition)?);
+                }
+            }
+        }
+        serde_json::from_value(value)
+    }
+
+    // The title on the first front page that has one
+    pub fn title(&self) -> &str {
+        self.front_pages
+            .iter()
+            .find_map(|page| match page {
+                Page::FrontPage(front_page) => Some(front_page.title.as_str()),
+                _ => None,
+            })
+            .unwrap_or("")
+    }
+
+    // The songs in book order, each with the number it's printed with
+    pub fn numbered_songs(&self) -> Vec<(String, &Song)> {
+        let mut numbered = Vec::new();
+        let mut prefix = "";
+        let mut number = 0;
+        for item in &self.songs {
+            match item {
+                SongListItem::Section { section } => {
+                    if self.song_numbering == SongNumbering::PerSection {
+                        prefix = &section.prefix;
+                        number = 0;
+                    }
+                }
+                SongListItem::Song(song) => {
+                    number += 1;
+                    let label = if prefix.is_empty() {
+                        number.to_string()
+                    } else {
+                        format!("{}-{}", prefix, number)
+                    };
+                    numbered.push((label, song));
+                }
+            }
+        }
+        numbered
+    }
+
+    // Settings that can't be laid out, such as a custom page size of zero, which would fit
+    // endlessly many pages on each sheet
+    pub fn validate(&self) -> Result<(), GenerationError> {
+        let sizes = [
+            ("page", self.tiled_page_size.size_mm()),
+            ("sheet", self.sheet_size.size_mm()),
+        ];
+        for (name, (width, height)) in sizes {
+            if !(width.is_finite() && height.is_finite() && width > 0.0 && height > 0.0) {
+                return Err(GenerationError::ConfigError(format!(
+                    "Invalid {} size {} × {} mm",
+                    name, width, height
+                )));
+            }
         }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn song_list() -> Vec<SongListItem> {
+        let song = |title: &str| {
+            SongListItem::Song(Song {
+                title: title.to_owned(),
+                body: vec![],
+                tags: HashMap::new(),
+                columns: None,
+            })
+        };
+        let section = |title: &str, prefix: &str| SongListItem::Section {
+            section: Section {
+                title: title.to_owned(),
+                prefix: prefix.to_owned(),
+                ..Default::default()
+            },
+        };
+        vec![
+            song("A"),
+            section("Jólalög", "J"),
+            song("B"),
+            song("C"),
+            section("Varðeldur", ""),
+            song("D"),
+        ]
+    }
+
+    fn labels(config: &BookConfig) -> Vec<String> {
+        config
+            .numbered_songs()
+            .into_iter()
+            .map(|(label, _)| label)
+            .collect()
+    }
+
+    #[test]
+    fn test_numbered_songs_continuous() {
+        let config = BookConfig {
+            songs: song_list(),
+            ..Default::default()
+        };
+        assert_eq!(labels(&config), ["1", "2", "3", "4"]);
+    }
+
+    #[test]
+    fn test_numbered_songs_per_section() {
+        let config = BookConfig {
+            songs: song_list(),
+            song_numbering: SongNumbering::PerSection,
+            ..Default::default()
+        };
+        assert_eq!(labels(&config), ["1", "J-1", "J-2", "1"]);
+    }
+
+    #[test]
+    fn test_external_pdf_page_indices() {
+        let pdf = |pages: &str| ExternalPdf {
+            path: "kort.pdf".to_owned(),
+            pages: pages.to_owned(),
+        };
+        assert_eq!(pdf("").page_indices(), Ok(None));
+        assert_eq!(pdf("2").page_indices(), Ok(Some(vec![1])));
+        assert_eq!(
+            pdf("1-3, 6 ,5").page_indices(),
+            Ok(Some(vec![0, 1, 2, 5, 4]))
+        );
+        assert!(pdf("0").page_indices().is_err());
+        assert!(pdf("1-a").page_indices().is_err());
+        assert!(pdf("5-3").page_indices().is_err());
+        assert_eq!(pdf("4-4").page_indices(), Ok(Some(vec![3])));
+    }
+
+    #[test]
+    fn test_validate_sizes() {
+        assert!(BookConfig::default().validate().is_ok());
+        let config = BookConfig {
+            tiled_page_size: tile::PageSize::Custom {
+                width_mm: 0,
+                height_mm: 105,
+            },
+            ..Default::default()
+        };
+        assert!(matches!(
+            config.validate(),
+            Err(GenerationError::ConfigError(_))
+        ));
+        let config = BookConfig {
+            sheet_size: tile::PaperSize::Custom {
+                width_mm: 210,
+                height_mm: 0,
+            },
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_reorder_pages_migrates_to_imposition() {
+        let imposition = |settings: &str| {
+            let json = format!(
+                r#"{{"front_pages": [], "back_pages": [], "songs": [], "preferred_font": "Arial"{}}}"#,
+                settings
+            );
+            BookConfig::from_json(&json).unwrap().imposition
+        };
+        assert_eq!(
+            imposition(r#", "reorder_pages": false"#),
+            Imposition::Sequential
+        );
+        assert_eq!(
+            imposition(r#", "reorder_pages": true"#),
+            Imposition::Booklet
+        );
+        assert_eq!(
+            imposition(r#", "reorder_pages": false, "imposition": "CutAndStack""#),
+            Imposition::CutAndStack
+        );
+        assert_eq!(imposition(""), Imposition::Booklet);
+    }
+
+    #[test]
+    fn test_song_list_loads_old_format() {
+        let items: Vec<SongListItem> =
+            serde_json::from_str(r#"[{"title": "A"}, {"section": {"title": "Jól"}}]"#).unwrap();
+        assert!(matches!(&items[0], SongListItem::Song(s) if s.title == "A"));
+        assert!(matches!(&items[1], SongListItem::Section { section } if section.title == "Jól"));
     }
 }
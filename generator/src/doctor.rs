@@ -0,0 +1,162 @@
+use std::{fmt, fs, path::Path};
+
+use crate::config::{BookConfig, PdfBackend};
+use crate::{bind_pdfium, gen_pdfs, parse_song_body, GenerationError, SONGS_DIR};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    Ok,
+    // Something that's missing but not needed with these settings
+    Warning,
+    Failed,
+}
+
+// One of the things making a book depends on, and whether it's in place
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub message: String,
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self.status {
+            CheckStatus::Ok => "ok",
+            CheckStatus::Warning => "warning",
+            CheckStatus::Failed => "FAILED",
+        };
+        write!(f, "[{}] {}: {}", status, self.name, self.message)
+    }
+}
+
+// Checks everything that's needed before generating, so it doesn't fail halfway through
+pub fn run_checks(config: &BookConfig) -> Vec<Check> {
+    vec![
+        check_pdfium(config),
+        check_font(config),
+        check_songs(Path::new(SONGS_DIR)),
+    ]
+}
+
+fn check_pdfium(config: &BookConfig) -> Check {
+    let (status, message) = match bind_pdfium(config) {
+        Ok((_, place)) => (CheckStatus::Ok, format!("Loaded from {}", place)),
+        Err(e) if config.pdf_backend == PdfBackend::Native => (
+            CheckStatus::Warning,
+            format!("Not found, but only needed with the Pdfium backend\n{}", e),
+        ),
+        Err(e) => (CheckStatus::Failed, e.to_string()),
+    };
+    Check {
+        name: "Pdfium",
+        status,
+        message,
+    }
+}
+
+fn check_font(config: &BookConfig) -> Check {
+    let (status, message) = match gen_pdfs::load_font(&config.preferred_font) {
+        Ok(_) => (
+            CheckStatus::Ok,
+            format!("Loaded \"{}\"", config.preferred_font),
+        ),
+        Err(e) => (
+            CheckStatus::Failed,
+            format!("Failed to load \"{}\": {}", config.preferred_font, e),
+        ),
+    };
+    Check {
+        name: "Font",
+        status,
+        message,
+    }
+}
+
+// Every file in the songs directory should be a song that can be read
+fn check_songs(dir: &Path) -> Check {
+    let check = |status, message| Check {
+        name: "Songs",
+        status,
+        message,
+    };
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            return check(
+                CheckStatus::Failed,
+                format!("Failed to read {}: {}", dir.display(), e),
+            )
+        }
+    };
+
+    let mut count = 0;
+    let mut problems = Vec::new();
+    for entry in entries {
+        let path = match entry {
+            Ok(entry) => entry.path(),
+            Err(e) => {
+                problems.push(e.to_string());
+                continue;
+            }
+        };
+        if !path.is_file() {
+            continue;
+        }
+        count += 1;
+        let title = path.file_stem().unwrap_or_default().to_string_lossy();
+        let result = fs::read_to_string(&path)
+            .map_err(GenerationError::from)
+            .and_then(|body| parse_song_body(title, &body));
+        if let Err(e) = result {
+            problems.push(format!("{}: {}", path.display(), e));
+        }
+    }
+
+    if !problems.is_empty() {
+        let mut message = format!("{} of {} songs can't be read:", problems.len(), count);
+        for problem in problems {
+            message.push_str("\n  ");
+            message.push_str(&problem);
+        }
+        check(CheckStatus::Failed, message)
+    } else if count == 0 {
+        check(
+            CheckStatus::Warning,
+            format!("There are no songs in {}", dir.display()),
+        )
+    } else {
+        check(
+            CheckStatus::Ok,
+            format!("{} songs in {}", count, dir.display()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_songs() {
+        let dir = std::env::temp_dir().join("skata-songbok-doctor-test");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            check_songs(&dir.join("missing")).status,
+            CheckStatus::Failed
+        );
+        assert_eq!(check_songs(&dir).status, CheckStatus::Warning);
+
+        fs::write(dir.join("Lag.txt"), "Fyrsta lína\nönnur lína\n").unwrap();
+        assert_eq!(check_songs(&dir).status, CheckStatus::Ok);
+
+        fs::write(dir.join("Bilað.txt"), "Lína\n----\nenginn tvípunktur\n").unwrap();
+        let check = check_songs(&dir);
+        assert_eq!(check.status, CheckStatus::Failed);
+        assert!(check.message.starts_with("1 of 2 songs"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
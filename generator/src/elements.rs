@@ -0,0 +1,174 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+use genpdf::error::Error;
+use genpdf::style::{Color, LineStyle, Style};
+use genpdf::{render, Context, Element, Margins, Mm, PageDecorator, Position, RenderResult, Size};
+
+// Evenly spaced lines to write on, one text line apart, continuing on the next page if needed
+pub struct RuledLines {
+    remaining: u16,
+}
+
+impl RuledLines {
+    pub fn new(lines: u16) -> RuledLines {
+        RuledLines { remaining: lines }
+    }
+}
+
+impl Element for RuledLines {
+    fn render(
+        &mut self,
+        context: &Context,
+        area: render::Area<'_>,
+        style: Style,
+    ) -> Result<RenderResult, Error> {
+        let line_height = style.line_height(&context.font_cache);
+        let line_style = LineStyle::new()
+            .with_thickness(0.2)
+            .with_color(Color::Greyscale(160));
+        // A zero size when not even one line fits tells the document it's too big for any page
+        let mut result = RenderResult::default();
+
+        while self.remaining > 0 {
+            let y = result.size.height + line_height;
+            if y > area.size().height {
+                result.has_more = true;
+                break;
+            }
+            area.draw_line(
+                vec![Position::new(0, y), Position::new(area.size().width, y)],
+                line_style,
+            );
+            result.size = Size::new(area.size().width, y);
+            self.remaining -= 1;
+        }
+        Ok(result)
+    }
+}
+
+// Counts the pages of the document it decorates, so the page count is known after rendering
+pub struct PageCounter<D: PageDecorator> {
+    inner: D,
+    pages: Rc<Cell<usize>>,
+}
+
+impl<D: PageDecorator> PageCounter<D> {
+    pub fn new(inner: D) -> (PageCounter<D>, Rc<Cell<usize>>) {
+        let pages = Rc::new(Cell::new(0));
+        let counter = PageCounter {
+            inner,
+            pages: pages.clone(),
+        };
+        (counter, pages)
+    }
+}
+
+impl<D: PageDecorator> PageDecorator for PageCounter<D> {
+    fn decorate_page<'a>(
+        &mut self,
+        context: &Context,
+        area: render::Area<'a>,
+        style: Style,
+    ) -> Result<render::Area<'a>, Error> {
+        self.pages.set(self.pages.get() + 1);
+        self.inner.decorate_page(context, area, style)
+    }
+}
+
+// Records the page its element starts on, counted from zero, using the count from a PageCounter
+pub struct PageMarker<E: Element> {
+    inner: E,
+    pages: Rc<Cell<usize>>,
+    page: Rc<Cell<Option<usize>>>,
+}
+
+impl<E: Element> PageMarker<E> {
+    pub fn new(inner: E, pages: Rc<Cell<usize>>) -> (PageMarker<E>, Rc<Cell<Option<usize>>>) {
+        let page = Rc::new(Cell::new(None));
+        let marker = PageMarker {
+            inner,
+            pages,
+            page: page.clone(),
+        };
+        (marker, page)
+    }
+}
+
+impl<E: Element> Element for PageMarker<E> {
+    fn render(
+        &mut self,
+        context: &Context,
+        area: render::Area<'_>,
+        style: Style,
+    ) -> Result<RenderResult, Error> {
+        let result = self.inner.render(context, area, style)?;
+        // Nothing is drawn when the element doesn't fit at the bottom of a page, and then it
+        // starts on the next one
+        let started = result.size.height > Mm::default() || !result.has_more;
+        if self.page.get().is_none() && started {
+            self.page.set(Some(self.pages.get().saturating_sub(1)));
+        }
+        Ok(result)
+    }
+}
+
+// Lays the elements out in columns of equal width, filling each column before moving on to the
+// next one, and the last column before moving on to the next page
+pub struct Columns {
+    elements: Vec<Box<dyn Element>>,
+    columns: usize,
+    gap: Mm,
+    next: usize,
+}
+
+impl Columns {
+    pub fn new(elements: Vec<Box<dyn Element>>, columns: usize, gap: impl Into<Mm>) -> Columns {
+        Columns {
+            elements,
+            columns: columns.max(1),
+            gap: gap.into(),
+            next: 0,
+        }
+    }
+}
+
+impl Element for Columns {
+    fn render(
+        &mut self,
+        context: &Context,
+        area: render::Area<'_>,
+        style: Style,
+    ) -> Result<RenderResult, Error> {
+        let mut result = RenderResult::default();
+        let first = self.next;
+
+        let half_gap = self.gap / 2.0;
+        for mut column in area.split_horizontally(&vec![1; self.columns]) {
+            column.add_margins(Margins::trbl(0, half_gap, 0, half_gap));
+            let mut height = Mm::default();
+            while let Some(element) = self.elements.get_mut(self.next) {
+                let element_result = element.render(context, column.clone(), style)?;
+                column.add_offset(Position::new(0, element_result.size.height));
+                height += element_result.size.height;
+                // Whatever didn't fit continues at the top of the next column
+                if element_result.has_more {
+                    break;
+                }
+                self.next += 1;
+            }
+            result.size.height = result.size.height.max(height);
+            if self.next >= self.elements.len() {
+                break;
+            }
+        }
+
+        result.has_more = self.next < self.elements.len();
+        // When nothing fits, such as a verse taller than the columns, the size stays zero so the
+        // document knows it won't fit on the next page either
+        if self.next > first || result.size.height > Mm::default() {
+            result.size.width = area.size().width;
+        }
+        Ok(result)
+    }
+}
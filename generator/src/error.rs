@@ -0,0 +1,118 @@
+use std::{error::Error, fmt};
+
+use pdfium_render::prelude::PdfiumError;
+
+use crate::fonts::FontError;
+use crate::PDFIUM_PATH_VAR;
+
+// Everything that can go wrong while making a book, from reading the settings to saving the PDF
+#[derive(Debug)]
+pub enum GenerationError {
+    // The settings couldn't be read
+    ConfigParseError(serde_json::Error),
+    // The settings can't be used, e.g. a page range that's out of order
+    ConfigError(String),
+    // A song file couldn't be found or read
+    SongError(String),
+    IoError(std::io::Error),
+    FontError(FontError),
+    // genpdf failed to lay out a page, e.g. when the margins leave no room for the text
+    RenderError(genpdf::error::Error),
+    PdfiumError(PdfiumError),
+    // Each place Pdfium was looked for, with why it couldn't be loaded from there
+    PdfiumNotFound(Vec<String>),
+    LopdfError(lopdf::Error),
+    ImageError(image::ImageError),
+    // The generation was stopped with its cancel token
+    Cancelled,
+}
+
+impl From<serde_json::Error> for GenerationError {
+    fn from(value: serde_json::Error) -> Self {
+        Self::ConfigParseError(value)
+    }
+}
+
+impl From<std::io::Error> for GenerationError {
+    fn from(value: std::io::Error) -> Self {
+        Self::IoError(value)
+    }
+}
+
+impl From<FontError> for GenerationError {
+    fn from(value: FontError) -> Self {
+        Self::FontError(value)
+    }
+}
+
+impl From<genpdf::error::Error> for GenerationError {
+    fn from(value: genpdf::error::Error) -> Self {
+        Self::RenderError(value)
+    }
+}
+
+impl From<PdfiumError> for GenerationError {
+    fn from(value: PdfiumError) -> Self {
+        Self::PdfiumError(value)
+    }
+}
+
+impl From<lopdf::Error> for GenerationError {
+    fn from(value: lopdf::Error) -> Self {
+        Self::LopdfError(value)
+    }
+}
+
+impl From<image::ImageError> for GenerationError {
+    fn from(value: image::ImageError) -> Self {
+        Self::ImageError(value)
+    }
+}
+
+impl fmt::Display for GenerationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "GenerationError::")?;
+        match self {
+            Self::ConfigParseError(e) => write!(f, "ConfigParseError({})", e),
+            Self::ConfigError(e) => write!(f, "ConfigError({})", e),
+            Self::SongError(e) => write!(f, "SongError({})", e),
+            Self::IoError(e) => write!(f, "IoError({})", e),
+            Self::FontError(e) => write!(f, "FontError({})", e),
+            Self::RenderError(e) => write!(f, "RenderError({})", e),
+            Self::PdfiumError(e) => write!(f, "PdfiumError({})", e),
+            Self::PdfiumNotFound(tried) => {
+                writeln!(f, "PdfiumNotFound, tried:")?;
+                for place in tried {
+                    writeln!(f, "  {}", place)?;
+                }
+                write!(
+                    f,
+                    "Download Pdfium from https://github.com/bblanchon/pdfium-binaries and set \
+                     pdfium_path in the settings or the {} environment variable to where it is",
+                    PDFIUM_PATH_VAR
+                )
+            }
+            Self::LopdfError(e) => write!(f, "LopdfError({})", e),
+            Self::ImageError(e) => write!(f, "ImageError({})", e),
+            Self::Cancelled => write!(f, "Cancelled"),
+        }
+    }
+}
+
+impl Error for GenerationError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::ConfigParseError(e) => Some(e),
+            Self::IoError(e) => Some(e),
+            Self::FontError(e) => Some(e),
+            Self::RenderError(e) => Some(e),
+            Self::PdfiumError(e) => Some(e),
+            Self::LopdfError(e) => Some(e),
+            Self::ImageError(e) => Some(e),
+            Self::ConfigError(_)
+            | Self::SongError(_)
+            | Self::PdfiumNotFound(_)
+            | Self::Cancelled => None,
+        }
+    }
+}
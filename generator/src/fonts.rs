@@ -68,6 +68,14 @@ impl fmt::Display for FontError {
     }
 }
 
+impl std::error::Error for FontError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.win_err
+            .as_ref()
+            .map(|e| e as &(dyn std::error::Error + 'static))
+    }
+}
+
 impl From<windows::core::Error> for FontError {
     fn from(err: windows::core::Error) -> Self {
         FontError {
@@ -92,7 +100,8 @@ fn get_system_font_collection(check_for_updates: bool) -> Result<IDWriteFontColl
             .GetSystemFontCollection(&mut maybe_font_collection, check_for_updates)
             .map_err(|e| FontError::new("Failed to get system font collection", e))?;
     }
-    Ok(maybe_font_collection.unwrap())
+    maybe_font_collection
+        .ok_or_else(|| FontError::new_msg_only("The system font collection is missing"))
 }
 
 fn localize_string(strings: IDWriteLocalizedStrings) -> Result<String, FontError> {
@@ -170,7 +179,7 @@ fn get_font_data(font: IDWriteFont) -> Result<Vec<u8>, FontError> {
         // let factory = get_direct_write_factory()?;
         let mut maybe_file = None; // Some(factory.CreateFontFileReference(to_pcwstr(""), None)?);
         font_face.GetFiles(&mut num_files, Some(&mut maybe_file))?;
-        let file = maybe_file.unwrap();
+        let file = maybe_file.ok_or_else(|| FontError::new_msg_only("The font has no file"))?;
 
         let mut is_supported = false.into();
         let mut file_type = DWRITE_FONT_FILE_TYPE::default();
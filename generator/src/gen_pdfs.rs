@@ -1,30 +1,65 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::mem;
+use std::rc::Rc;
+
 use genpdf::Alignment;
 use genpdf::Element as _;
 use genpdf::{elements, fonts, style};
+use serde::{Deserialize, Serialize};
 
-use crate::config::TableOfContentsSortOrder;
-use crate::config::{FrontPage, Page, Preface, Song, TableOfContents};
+use crate::cache::{self, RenderCache};
+use crate::config::{
+    AuthorIndex, BookConfig, CustomPage, FrontPage, Notes, Page, Preface, Section, SectionStyle,
+    Song, SongLayout, SongListItem, SongNumbering, TableOfContents, TableOfContentsSortOrder,
+    Typography,
+};
+use crate::elements::{PageCounter, PageMarker};
 use crate::fonts::FontError;
+use crate::progress::SongCounter;
+use crate::tile::{self, PdfPart};
+use crate::{images, markdown, GenerationError};
+
+const COLUMN_GAP_MM: f64 = 4.0;
+
+// The pages are laid out at the size they're printed at, so the font sizes and margins are the
+// ones that end up on paper
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct PageFormat {
+    pub page_size: tile::PageSize,
+    pub typography: Typography,
+}
+
+impl PageFormat {
+    pub fn new(config: &BookConfig) -> PageFormat {
+        PageFormat {
+            page_size: config.tiled_page_size,
+            typography: config.typography,
+        }
+    }
 
-const TITLE_FONT_SIZE: u8 = 36;
-const SONG_TITLE_FONT_SIZE: u8 = 28;
-const BODY_FONT_SIZE: u8 = 24;
+    fn text_width_mm(&self) -> f64 {
+        self.page_size.size_mm().0 - 2.0 * self.typography.margins_mm as f64
+    }
+}
 
-type Font = fonts::FontFamily<fonts::FontData>;
+pub type Font = fonts::FontFamily<fonts::FontData>;
 pub fn load_font(font_name: &str) -> Result<Font, FontError> {
     crate::fonts::get_font(font_name)
 }
 
-fn get_empty_pdf(font: &Font) -> genpdf::Document {
+fn get_counted_pdf(font: &Font, format: &PageFormat) -> (genpdf::Document, Rc<Cell<usize>>) {
     // Configure the document
     let mut doc = genpdf::Document::new(font.clone());
+    let (width, height) = format.page_size.size_mm();
+    doc.set_paper_size(genpdf::Size::new(width, height));
     doc.set_title("temp");
     doc.set_minimal_conformance();
     doc.set_line_spacing(1.25);
 
     // Add the line around the page
     let mut decorator = genpdf::SimplePageDecorator::new();
-    decorator.set_margins(10);
+    decorator.set_margins(format.typography.margins_mm as f64);
     // decorator.set_header(|page| {
     //     let mut layout = elements::LinearLayout::vertical();
     //     if page > 1 {
@@ -35,95 +70,624 @@ fn get_empty_pdf(font: &Font) -> genpdf::Document {
     //     }
     //     layout.styled(style::Style::new().with_font_size(10))
     // });
+    let (decorator, pages) = PageCounter::new(decorator);
     doc.set_page_decorator(decorator);
-    return doc;
+    return (doc, pages);
 }
 
-pub fn pdf_to_bytes(doc: genpdf::Document) -> Vec<u8> {
+fn get_empty_pdf(font: &Font, format: &PageFormat) -> genpdf::Document {
+    get_counted_pdf(font, format).0
+}
+
+pub fn pdf_to_bytes(doc: genpdf::Document) -> Result<Vec<u8>, genpdf::error::Error> {
     // Render the PDF to bytes
     let mut bytes = Vec::new();
-    doc.render(&mut bytes).expect("Failed to render PDF");
-    return bytes;
+    doc.render(&mut bytes)?;
+    return Ok(bytes);
 }
 
-pub fn generate_songs(font: &Font, songs: &[Song]) -> Vec<u8> {
-    let mut doc = get_empty_pdf(font);
-
-    for (i, song) in songs.iter().enumerate() {
-        // Generate the title on the first page
-        let mut layout = elements::GlueLayout::vertical();
-        layout.push(
-            elements::Paragraph::new(format!("{}. {}", i + 1, song.title))
+fn generate_section(doc: &mut genpdf::Document, format: &PageFormat, section: &Section) {
+    doc.push(
+        elements::Paragraph::new(&section.title)
+            .aligned(Alignment::Center)
+            .styled(
+                style::Style::new()
+                    .bold()
+                    .with_font_size(format.typography.title_font_size),
+            ),
+    );
+    for line in section.description.lines() {
+        doc.push(
+            elements::Paragraph::new(line)
                 .aligned(Alignment::Center)
-                .styled(style::Style::new().with_font_size(SONG_TITLE_FONT_SIZE)),
+                .styled(
+                    style::Style::new()
+                        .italic()
+                        .with_font_size(format.typography.body_font_size),
+                ),
         );
+    }
+    doc.push(elements::Break::new(1.5));
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SongStyle {
+    font_size: u8,
+    columns: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TitlePlacement {
+    // Kept together with the first verse, at the top of the first column when there are columns
+    WithFirstVerse,
+    InColumn,
+    // Spanning all the columns
+    AboveColumns,
+}
+
+fn title_placement(layout: &SongLayout, song_style: SongStyle) -> TitlePlacement {
+    if layout.keep_title_with_verse {
+        TitlePlacement::WithFirstVerse
+    } else if song_style.columns == 1 {
+        TitlePlacement::InColumn
+    } else {
+        TitlePlacement::AboveColumns
+    }
+}
+
+// Returns the page the song's title ends up on once the document is rendered
+fn push_song(
+    doc: &mut genpdf::Document,
+    pages: &Rc<Cell<usize>>,
+    format: &PageFormat,
+    num: &str,
+    song: &Song,
+    layout: &SongLayout,
+    song_style: SongStyle,
+) -> Rc<Cell<Option<usize>>> {
+    // Shrink the title along with the song text
+    let typography = &format.typography;
+    let title_font_size = (typography.song_title_font_size as u16 * song_style.font_size as u16
+        / typography.body_font_size.max(1) as u16) as u8;
+    let title = elements::Paragraph::new(format!("{}. {}", num, song.title))
+        .aligned(Alignment::Center)
+        .styled(style::Style::new().with_font_size(title_font_size));
+    let (title, title_page) = PageMarker::new(title, pages.clone());
 
-        // Generate the song lines
-        for (_, verse) in song.body.iter().enumerate() {
-            for line in verse.lines() {
-                layout.push(
-                    elements::Text::new(line)
-                        .styled(style::Style::new().with_font_size(BODY_FONT_SIZE)),
-                );
+    let placement = title_placement(layout, song_style);
+    let title_in_glue = placement == TitlePlacement::WithFirstVerse;
+    let mut blocks: Vec<Box<dyn genpdf::Element>> = Vec::new();
+    let mut glued = elements::GlueLayout::vertical();
+    match placement {
+        TitlePlacement::WithFirstVerse => {
+            glued.push(title);
+            if song.body.is_empty() {
+                blocks.push(Box::new(glued));
+                glued = elements::GlueLayout::vertical();
             }
-            layout.push(elements::Break::new(1.5));
-            doc.push(layout);
-            layout = elements::GlueLayout::vertical();
         }
+        TitlePlacement::InColumn => blocks.push(Box::new(title)),
+        TitlePlacement::AboveColumns => doc.push(title),
+    }
 
-        doc.push(elements::Break::new(1.0));
+    // Generate the song lines, keeping the verses that shouldn't be split on one page
+    for (i, verse) in song.body.iter().enumerate() {
+        let glue_verse = layout.keep_verses_together || (i == 0 && title_in_glue);
+        for line in verse.lines() {
+            let line = elements::Text::new(line)
+                .styled(style::Style::new().with_font_size(song_style.font_size));
+            if glue_verse {
+                glued.push(line);
+            } else {
+                blocks.push(Box::new(line));
+            }
+        }
+        if glue_verse {
+            glued.push(elements::Break::new(1.5));
+            blocks.push(Box::new(glued));
+            glued = elements::GlueLayout::vertical();
+        } else {
+            blocks.push(Box::new(elements::Break::new(1.5)));
+        }
     }
 
-    return pdf_to_bytes(doc);
+    if song_style.columns > 1 {
+        doc.push(crate::elements::Columns::new(
+            blocks,
+            song_style.columns,
+            COLUMN_GAP_MM,
+        ));
+    } else {
+        for block in blocks {
+            doc.push(block);
+        }
+    }
+    doc.push(elements::Break::new(1.0));
+    title_page
 }
 
-pub fn generate_preface(doc: &mut genpdf::Document, preface: &Preface) {
+// Where a song starts in the part of the book it's laid out in, counted from zero
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SongStart {
+    pub page: usize,
+    pub label: String,
+}
+
+// What was found out while laying out a part of the book, which is cached along with it
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PartInfo {
+    pub song_starts: Vec<SongStart>,
+    // Songs that couldn't be laid out as the settings ask
+    pub warnings: Vec<String>,
+}
+
+// A part of the book as it's laid out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LaidOutPart {
+    pub pdf: PdfPart,
+    pub info: PartInfo,
+}
+
+impl From<PdfPart> for LaidOutPart {
+    fn from(pdf: PdfPart) -> Self {
+        LaidOutPart {
+            pdf,
+            info: PartInfo::default(),
+        }
+    }
+}
+
+// A document songs are laid out in, which keeps track of where each of them starts
+struct SongDoc {
+    doc: genpdf::Document,
+    pages: Rc<Cell<usize>>,
+    song_starts: Vec<(Rc<Cell<Option<usize>>>, String)>,
+    warnings: Vec<String>,
+}
+
+impl SongDoc {
+    fn new(font: &Font, format: &PageFormat) -> SongDoc {
+        let (doc, pages) = get_counted_pdf(font, format);
+        SongDoc {
+            doc,
+            pages,
+            song_starts: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    fn push_song(
+        &mut self,
+        format: &PageFormat,
+        num: &str,
+        song: &Song,
+        layout: &SongLayout,
+        song_style: SongStyle,
+    ) {
+        let title_page = push_song(
+            &mut self.doc,
+            &self.pages,
+            format,
+            num,
+            song,
+            layout,
+            song_style,
+        );
+        self.song_starts
+            .push((title_page, format!("{}. {}", num, song.title)));
+    }
+
+    fn render(self, spread: bool) -> Result<LaidOutPart, genpdf::error::Error> {
+        let bytes = pdf_to_bytes(self.doc)?;
+        Ok(LaidOutPart {
+            pdf: if spread {
+                PdfPart::Spread(bytes)
+            } else {
+                PdfPart::Generated(bytes)
+            },
+            info: PartInfo {
+                song_starts: self
+                    .song_starts
+                    .into_iter()
+                    .filter_map(|(page, label)| {
+                        Some(SongStart {
+                            page: page.get()?,
+                            label,
+                        })
+                    })
+                    .collect(),
+                warnings: self.warnings,
+            },
+        })
+    }
+}
+
+// The headings are the ones that go on the same page as the song, above it
+fn count_song_pages(
+    font: &Font,
+    format: &PageFormat,
+    headings: &[&Section],
+    num: &str,
+    song: &Song,
+    layout: &SongLayout,
+    song_style: SongStyle,
+) -> Option<usize> {
+    let mut doc = SongDoc::new(font, format);
+    for heading in headings {
+        generate_section(&mut doc.doc, format, heading);
+    }
+    doc.push_song(format, num, song, layout, song_style);
+    doc.doc
+        .render(&mut std::io::sink())
+        .ok()
+        .map(|()| doc.pages.get())
+}
+
+// Shrink the font step by step, and then try two columns, until the song fits on one page
+fn fit_song(
+    font: &Font,
+    format: &PageFormat,
+    headings: &[&Section],
+    num: &str,
+    song: &Song,
+    layout: &SongLayout,
+    columns: usize,
+) -> Option<SongStyle> {
+    let body_font_size = format.typography.body_font_size;
+    let min_font_size = layout.min_font_size.min(body_font_size);
+    let column_counts = if columns > 1 {
+        vec![columns]
+    } else {
+        vec![1, 2]
+    };
+    for columns in column_counts {
+        for font_size in (min_font_size..=body_font_size).rev() {
+            let song_style = SongStyle { font_size, columns };
+            if count_song_pages(font, format, headings, num, song, layout, song_style) == Some(1) {
+                return Some(song_style);
+            }
+        }
+    }
+    None
+}
+
+// A part of the song list that's laid out on its own, with the numbers of the songs in it
+#[derive(Debug, Clone, Copy)]
+pub struct SongSection<'a> {
+    pub items: &'a [SongListItem],
+    pub numbers: &'a [String],
+}
+
+// The song list split before each section that starts on a new page, along with the headings
+// right before it, and after each song when every song starts on a new page. Nothing carries over
+// from one part to the next, so each can be laid out on its own.
+pub fn song_sections<'a>(config: &'a BookConfig, numbers: &'a [String]) -> Vec<SongSection<'a>> {
+    let items = config.songs.as_slice();
+    let songs_on_own_pages = config.song_layout.new_page_per_song || config.song_layout.fit_to_page;
+    let mut splits = Vec::new();
+    let mut headings_start = None;
+    for (i, item) in items.iter().enumerate() {
+        match item {
+            SongListItem::Section { section } if section.style == SectionStyle::Heading => {
+                headings_start.get_or_insert(i);
+            }
+            SongListItem::Section { .. } => splits.push(headings_start.take().unwrap_or(i)),
+            SongListItem::Song(_) => {
+                headings_start = None;
+                if songs_on_own_pages {
+                    splits.push(i + 1);
+                }
+            }
+        }
+    }
+    splits.push(items.len());
+
+    let mut sections = Vec::new();
+    let (mut start, mut first_song) = (0, 0);
+    for split in splits {
+        if split <= start {
+            continue;
+        }
+        let items = &items[start..split];
+        let song_count = items
+            .iter()
+            .filter(|item| matches!(item, SongListItem::Song(_)))
+            .count();
+        sections.push(SongSection {
+            items,
+            numbers: &numbers[first_song..first_song + song_count],
+        });
+        start = split;
+        first_song += song_count;
+    }
+    sections
+}
+
+// The text and tags of a song aren't saved with the settings, so they're added to the keys on
+// their own
+type SongContent<'a> = (&'a [String], &'a HashMap<String, Vec<String>>);
+
+fn song_contents(items: &[SongListItem]) -> Vec<SongContent<'_>> {
+    items
+        .iter()
+        .filter_map(|item| match item {
+            SongListItem::Song(song) => Some((song.body.as_slice(), &song.tags)),
+            SongListItem::Section { .. } => None,
+        })
+        .collect()
+}
+
+fn section_key(config: &BookConfig, section: &SongSection) -> u64 {
+    cache::cache_key(&SectionKey {
+        items: section.items,
+        contents: song_contents(section.items),
+        numbers: section.numbers,
+        font: &config.preferred_font,
+        format: &PageFormat::new(config),
+        song_layout: &config.song_layout,
+        columns: config.columns,
+    })
+}
+
+// Everything a section of songs is laid out from, to know when it has to be laid out again
+#[derive(Serialize)]
+struct SectionKey<'a> {
+    items: &'a [SongListItem],
+    contents: Vec<SongContent<'a>>,
+    numbers: &'a [String],
+    font: &'a str,
+    format: &'a PageFormat,
+    song_layout: &'a SongLayout,
+    columns: u8,
+}
+
+// The section is taken from the cache when nothing it's laid out from has changed
+pub fn generate_songs(
+    font: &Font,
+    config: &BookConfig,
+    section: &SongSection,
+    cache: &RenderCache,
+    songs: &SongCounter,
+) -> Result<Vec<LaidOutPart>, GenerationError> {
+    let key = section_key(config, section);
+    let parts = match cache.get::<Vec<PartInfo>>(key) {
+        Some((pdfs, infos)) => {
+            for _ in section.numbers {
+                songs.song_done()?;
+            }
+            pdfs.into_iter()
+                .zip(infos)
+                .map(|(pdf, info)| LaidOutPart { pdf, info })
+                .collect()
+        }
+        None => {
+            let parts = generate_song_section(font, config, section, songs)?;
+            let (pdfs, infos): (Vec<_>, Vec<_>) = parts
+                .iter()
+                .map(|part| (part.pdf.clone(), part.info.clone()))
+                .unzip();
+            if let Err(e) = cache.insert(key, &pdfs, &infos) {
+                println!("Failed to cache the rendered songs: {}", e);
+            }
+            parts
+        }
+    };
+    // The warnings are given again when the section comes from the cache, until it's fixed
+    for warning in parts.iter().flat_map(|part| &part.info.warnings) {
+        songs.warn(warning);
+    }
+    Ok(parts)
+}
+
+// Songs are split into several PDFs where they have to start on a new page
+fn generate_song_section(
+    font: &Font,
+    config: &BookConfig,
+    section: &SongSection,
+    songs: &SongCounter,
+) -> Result<Vec<LaidOutPart>, GenerationError> {
+    let layout = &config.song_layout;
+    let format = PageFormat::new(config);
+    let body_font_size = format.typography.body_font_size;
+    let mut parts: Vec<LaidOutPart> = Vec::new();
+    let mut doc = SongDoc::new(font, &format);
+    let mut doc_is_empty = true;
+    let mut numbers = section.numbers.iter().cloned();
+    // Section headings are kept back so they end up on the same page as the song after them
+    let mut headings = Vec::new();
+
+    for item in section.items {
+        let song = match item {
+            SongListItem::Section { section } if section.style == SectionStyle::Heading => {
+                headings.push(section);
+                continue;
+            }
+            SongListItem::Section { section } => {
+                if !doc_is_empty {
+                    parts.push(mem::replace(&mut doc, SongDoc::new(font, &format)).render(false)?);
+                }
+                for heading in headings.drain(..) {
+                    generate_section(&mut doc.doc, &format, heading);
+                }
+                generate_section(&mut doc.doc, &format, section);
+                parts.push(mem::replace(&mut doc, SongDoc::new(font, &format)).render(false)?);
+                doc_is_empty = true;
+                continue;
+            }
+            SongListItem::Song(song) => song,
+        };
+        let num = numbers.next().unwrap_or_default();
+
+        let columns = song.columns.unwrap_or(config.columns).max(1) as usize;
+        let mut warning = None;
+        let song_style = if layout.fit_to_page {
+            fit_song(font, &format, &headings, &num, song, layout, columns).unwrap_or_else(|| {
+                let smallest = SongStyle {
+                    font_size: layout.min_font_size.min(body_font_size),
+                    columns: columns.max(2),
+                };
+                warning = Some(format!(
+                    "Song \"{}\" doesn't fit on one page, even in {} columns at {}pt",
+                    song.title, smallest.columns, smallest.font_size
+                ));
+                smallest
+            })
+        } else {
+            SongStyle {
+                font_size: body_font_size,
+                columns,
+            }
+        };
+        let is_spread = layout.long_songs_on_even_page
+            && count_song_pages(font, &format, &headings, &num, song, layout, song_style)
+                .is_some_and(|pages| pages > 1);
+        let on_own_pages = layout.new_page_per_song || layout.fit_to_page || is_spread;
+        if on_own_pages && !doc_is_empty {
+            parts.push(mem::replace(&mut doc, SongDoc::new(font, &format)).render(false)?);
+        }
+
+        for heading in headings.drain(..) {
+            generate_section(&mut doc.doc, &format, heading);
+        }
+        doc.push_song(&format, &num, song, layout, song_style);
+        doc.warnings.extend(warning);
+        doc_is_empty = false;
+
+        if on_own_pages {
+            parts.push(mem::replace(&mut doc, SongDoc::new(font, &format)).render(is_spread)?);
+            doc_is_empty = true;
+        }
+
+        songs.song_done()?;
+    }
+
+    for heading in headings {
+        generate_section(&mut doc.doc, &format, heading);
+        doc_is_empty = false;
+    }
+    if !doc_is_empty {
+        parts.push(doc.render(false)?);
+    }
+    return Ok(parts);
+}
+
+pub fn generate_preface(
+    doc: &mut genpdf::Document,
+    format: &PageFormat,
+    preface: &Preface,
+) -> Result<(), GenerationError> {
     doc.push(
         elements::Paragraph::new(&preface.title)
             .aligned(Alignment::Center)
-            .styled(style::Style::new().bold().with_font_size(TITLE_FONT_SIZE)),
+            .styled(
+                style::Style::new()
+                    .bold()
+                    .with_font_size(format.typography.title_font_size),
+            ),
     );
     doc.push(elements::Break::new(1.5));
+    if preface.markdown {
+        doc.push(markdown::render(
+            &preface.body,
+            format.typography.body_font_size,
+            format.text_width_mm(),
+        )?);
+        return Ok(());
+    }
     for line in preface.body.lines() {
         doc.push(
             elements::Paragraph::new(line)
                 .aligned(Alignment::Left)
-                .styled(style::Style::new().with_font_size(BODY_FONT_SIZE)),
+                .styled(style::Style::new().with_font_size(format.typography.body_font_size)),
         );
     }
+    Ok(())
 }
 
-pub fn generate_front_page(doc: &mut genpdf::Document, front_page: &FrontPage) {
+pub fn generate_custom_page(
+    doc: &mut genpdf::Document,
+    format: &PageFormat,
+    page: &CustomPage,
+) -> Result<(), GenerationError> {
+    if !page.title.is_empty() {
+        doc.push(
+            elements::Paragraph::new(&page.title)
+                .aligned(Alignment::Center)
+                .styled(
+                    style::Style::new()
+                        .bold()
+                        .with_font_size(format.typography.title_font_size),
+                ),
+        );
+        doc.push(elements::Break::new(1.5));
+    }
+    doc.push(markdown::render(
+        &page.body,
+        format.typography.body_font_size,
+        format.text_width_mm(),
+    )?);
+    Ok(())
+}
+
+pub fn generate_notes(doc: &mut genpdf::Document, format: &PageFormat, notes: &Notes) {
+    if !notes.title.is_empty() {
+        doc.push(
+            elements::Paragraph::new(&notes.title)
+                .aligned(Alignment::Center)
+                .styled(
+                    style::Style::new()
+                        .bold()
+                        .with_font_size(format.typography.title_font_size),
+                ),
+        );
+    }
+    doc.push(
+        crate::elements::RuledLines::new(notes.lines)
+            .styled(style::Style::new().with_font_size(format.typography.body_font_size)),
+    );
+}
+
+pub fn generate_front_page(
+    doc: &mut genpdf::Document,
+    format: &PageFormat,
+    front_page: &FrontPage,
+) -> Result<(), GenerationError> {
     doc.push(
         elements::Paragraph::new(&front_page.title)
             .aligned(Alignment::Center)
-            .styled(style::Style::new().bold().with_font_size(TITLE_FONT_SIZE)),
+            .styled(
+                style::Style::new()
+                    .bold()
+                    .with_font_size(format.typography.title_font_size),
+            ),
     );
     doc.push(elements::Break::new(1.5));
+    for image in &front_page.images {
+        doc.push(images::from_config(image, format.text_width_mm())?);
+        doc.push(elements::Break::new(1.5));
+    }
     doc.push(
         elements::Paragraph::new(&front_page.version)
             .aligned(Alignment::Center)
-            .styled(style::Style::new().with_font_size(BODY_FONT_SIZE)),
+            .styled(style::Style::new().with_font_size(format.typography.body_font_size)),
     );
+    Ok(())
 }
 
 pub fn generate_table_of_contents(
     doc: &mut genpdf::Document,
-    songs: &[Song],
+    format: &PageFormat,
+    config: &BookConfig,
     toc: &TableOfContents,
 ) {
-    let mut songs_and_numbers = songs
-        .iter()
-        .enumerate()
-        .map(|(num, song)| (num + 1, song))
-        .collect::<Vec<_>>();
+    let mut songs_and_numbers = config.numbered_songs();
     match toc.order {
         TableOfContentsSortOrder::SongNumber => {}
         TableOfContentsSortOrder::Alphabetical => {
             songs_and_numbers.sort_by(|a, b| a.1.title.cmp(&b.1.title));
         }
     };
-    let format_song_title: Box<dyn Fn(usize, &str) -> String> = Box::new(match toc.order {
+    let format_song_title: Box<dyn Fn(&str, &str) -> String> = Box::new(match toc.order {
         TableOfContentsSortOrder::SongNumber => |num, title| format!("{}. {}", num, title),
         TableOfContentsSortOrder::Alphabetical => |num, title| format!("{}. {}", title, num),
     });
@@ -131,27 +695,291 @@ pub fn generate_table_of_contents(
     doc.push(
         elements::Paragraph::new(&toc.title)
             .aligned(Alignment::Center)
-            .styled(style::Style::new().bold().with_font_size(TITLE_FONT_SIZE)),
+            .styled(
+                style::Style::new()
+                    .bold()
+                    .with_font_size(format.typography.title_font_size),
+            ),
+    );
+    doc.push(elements::Break::new(1.5));
+
+    let song_line = |num: &str, title: &str| -> Box<dyn genpdf::Element> {
+        Box::new(
+            elements::Text::new(format_song_title(num, title))
+                .styled(style::Style::new().with_font_size(format.typography.body_font_size)),
+        )
+    };
+    let mut lines: Vec<Box<dyn genpdf::Element>> = Vec::new();
+    match toc.order {
+        // List the section headings as well when the songs are in book order
+        TableOfContentsSortOrder::SongNumber => {
+            let mut numbers = songs_and_numbers.into_iter().map(|(num, _)| num);
+            for item in &config.songs {
+                match item {
+                    SongListItem::Section { section } => lines.push(Box::new(
+                        elements::Text::new(&section.title).styled(
+                            style::Style::new()
+                                .bold()
+                                .with_font_size(format.typography.body_font_size),
+                        ),
+                    )),
+                    SongListItem::Song(song) => {
+                        let num = numbers.next().unwrap_or_default();
+                        lines.push(song_line(&num, &song.title));
+                    }
+                }
+            }
+        }
+        TableOfContentsSortOrder::Alphabetical => {
+            for (num, song) in songs_and_numbers {
+                lines.push(song_line(&num, &song.title));
+            }
+        }
+    }
+
+    if toc.columns > 1 {
+        doc.push(crate::elements::Columns::new(
+            lines,
+            toc.columns as usize,
+            COLUMN_GAP_MM,
+        ));
+    } else {
+        for line in lines {
+            doc.push(line);
+        }
+    }
+}
+
+pub fn generate_author_index(
+    doc: &mut genpdf::Document,
+    format: &PageFormat,
+    config: &BookConfig,
+    index: &AuthorIndex,
+) {
+    doc.push(
+        elements::Paragraph::new(&index.title)
+            .aligned(Alignment::Center)
+            .styled(
+                style::Style::new()
+                    .bold()
+                    .with_font_size(format.typography.title_font_size),
+            ),
     );
     doc.push(elements::Break::new(1.5));
-    for (num, song) in songs_and_numbers {
+    for (name, numbers) in crate::authors::author_index(&config.numbered_songs()) {
         doc.push(
-            elements::Text::new(format_song_title(num, &song.title))
-                .styled(style::Style::new().with_font_size(BODY_FONT_SIZE)),
+            elements::Paragraph::new(format!("{}: {}", name, numbers.join(", ")))
+                .aligned(Alignment::Left)
+                .styled(style::Style::new().with_font_size(format.typography.body_font_size)),
         );
     }
 }
 
-pub fn generate_page(font: &Font, songs: &[Song], page: &Page) -> Vec<u8> {
-    let mut doc = get_empty_pdf(font);
+// The images a page shows, which it has to be laid out again for when they change
+fn page_images(page: &Page) -> Vec<String> {
+    match page {
+        Page::FrontPage(front_page) => front_page
+            .images
+            .iter()
+            .map(|image| image.path.clone())
+            .collect(),
+        Page::Preface(preface) if preface.markdown => markdown::image_paths(&preface.body),
+        Page::Custom(custom_page) => markdown::image_paths(&custom_page.body),
+        _ => Vec::new(),
+    }
+}
+
+#[derive(Serialize)]
+struct PageKey<'a> {
+    page: &'a Page,
+    font: &'a str,
+    format: &'a PageFormat,
+    images: Vec<(String, Option<(u64, u32)>)>,
+    // The lists of songs are laid out from the songs in the book
+    songs: Option<(&'a [SongListItem], Vec<SongContent<'a>>, SongNumbering)>,
+}
+
+pub fn generate_cached_page(
+    font: &Font,
+    config: &BookConfig,
+    page: &Page,
+    cache: &RenderCache,
+) -> Result<Vec<u8>, GenerationError> {
+    let songs = match page {
+        Page::TableOfContents(_) | Page::AuthorIndex(_) => Some((
+            config.songs.as_slice(),
+            song_contents(&config.songs),
+            config.song_numbering,
+        )),
+        _ => None,
+    };
+    let key = cache::cache_key(&PageKey {
+        page,
+        font: &config.preferred_font,
+        format: &PageFormat::new(config),
+        images: page_images(page)
+            .into_iter()
+            .map(|path| {
+                let stamp = cache::file_stamp(&path);
+                (path, stamp)
+            })
+            .collect(),
+        songs,
+    });
+    if let Some((cached, ())) = cache.get(key) {
+        if let [PdfPart::Generated(bytes)] = cached.as_slice() {
+            return Ok(bytes.clone());
+        }
+    }
+
+    let bytes = generate_page(font, config, page)?;
+    if let Err(e) = cache.insert(key, &[PdfPart::Generated(bytes.clone())], &()) {
+        println!("Failed to cache the rendered page: {}", e);
+    }
+    Ok(bytes)
+}
+
+pub fn generate_page(
+    font: &Font,
+    config: &BookConfig,
+    page: &Page,
+) -> Result<Vec<u8>, GenerationError> {
+    let format = PageFormat::new(config);
+    let mut doc = get_empty_pdf(font, &format);
 
     match page {
-        Page::Preface(preface) => generate_preface(&mut doc, preface),
-        Page::FrontPage(front_page) => generate_front_page(&mut doc, front_page),
+        Page::Preface(preface) => generate_preface(&mut doc, &format, preface)?,
+        Page::FrontPage(front_page) => generate_front_page(&mut doc, &format, front_page)?,
         Page::TableOfContents(table_of_contents) => {
-            generate_table_of_contents(&mut doc, songs, table_of_contents)
+            generate_table_of_contents(&mut doc, &format, config, table_of_contents)
+        }
+        Page::AuthorIndex(author_index) => {
+            generate_author_index(&mut doc, &format, config, author_index)
         }
+        Page::Custom(custom_page) => generate_custom_page(&mut doc, &format, custom_page)?,
+        Page::Notes(notes) => generate_notes(&mut doc, &format, notes),
+        // An empty document still renders a single blank page
+        Page::Blank => {}
+        // These pages are copied from the file when the book is merged
+        Page::ExternalPdf(_) => {}
+    }
+
+    return Ok(pdf_to_bytes(doc)?);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_section_key_has_song_text() {
+        let mut config = BookConfig::default();
+        let song = Song {
+            title: "Lag".to_owned(),
+            body: vec!["Fyrsta lína".to_owned()],
+            tags: HashMap::new(),
+            columns: None,
+        };
+        config.songs = vec![SongListItem::Song(song.clone())];
+        let numbers = ["1".to_owned()];
+        let key = |config: &BookConfig| section_key(config, &song_sections(config, &numbers)[0]);
+        let original = key(&config);
+
+        let mut changed = song.clone();
+        changed.body[0].push('!');
+        config.songs = vec![SongListItem::Song(changed)];
+        assert_ne!(key(&config), original);
+
+        let mut tagged = song;
+        tagged
+            .tags
+            .insert("höfundur".to_owned(), vec!["Jón".to_owned()]);
+        config.songs = vec![SongListItem::Song(tagged)];
+        assert_ne!(key(&config), original);
+    }
+
+    // The page the title of a two-column song lands on, after lines that leave room for the
+    // title but not for the first verse, and how many pages there are in all
+    fn two_column_title_page(font: &Font, keep_title_with_verse: bool) -> (usize, usize) {
+        let format = PageFormat::new(&BookConfig::default());
+        let layout = SongLayout {
+            keep_title_with_verse,
+            ..Default::default()
+        };
+        let song = Song {
+            title: "Lag".to_owned(),
+            body: vec![vec!["Lína"; 8].join("\n")],
+            tags: HashMap::new(),
+            columns: Some(2),
+        };
+        let song_style = SongStyle {
+            font_size: 10,
+            columns: 2,
+        };
+        let mut doc = SongDoc::new(font, &format);
+        doc.doc.push(
+            crate::elements::RuledLines::new(15).styled(style::Style::new().with_font_size(10)),
+        );
+        doc.push_song(&format, "1", &song, &layout, song_style);
+        let pages = doc.pages.clone();
+        let part = doc.render(false).unwrap();
+        (part.info.song_starts[0].page, pages.get())
     }
 
-    return pdf_to_bytes(doc);
+    #[test]
+    fn test_title_kept_with_verse_in_columns() {
+        let font = load_font("Arial").unwrap();
+        // The title moves to the next page along with the first verse, in the first column
+        assert_eq!(two_column_title_page(&font, true), (1, 2));
+        // Otherwise it spans the columns at the bottom of the first page
+        assert_eq!(two_column_title_page(&font, false), (0, 2));
+    }
+
+    #[test]
+    fn test_verse_taller_than_page() {
+        let font = load_font("Arial").unwrap();
+        let format = PageFormat::new(&BookConfig::default());
+        let song = Song {
+            title: "Langt lag".to_owned(),
+            body: vec![vec!["Lína"; 100].join("\n")],
+            tags: HashMap::new(),
+            columns: Some(2),
+        };
+        let song_style = SongStyle {
+            font_size: 10,
+            columns: 2,
+        };
+        // The verse is kept together, so it can't be laid out on any page
+        let pages = count_song_pages(
+            &font,
+            &format,
+            &[],
+            "1",
+            &song,
+            &SongLayout::default(),
+            song_style,
+        );
+        assert_eq!(pages, None);
+    }
+
+    #[test]
+    fn test_ruled_line_taller_than_page() {
+        let font = load_font("Arial").unwrap();
+        let config = BookConfig {
+            tiled_page_size: tile::PageSize::Custom {
+                width_mm: 74,
+                height_mm: 40,
+            },
+            typography: Typography {
+                body_font_size: 100,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let notes = Page::Notes(Notes {
+            title: String::new(),
+            lines: 3,
+        });
+        assert!(generate_page(&font, &config, &notes).is_err());
+    }
 }
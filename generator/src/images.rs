@@ -0,0 +1,69 @@
+use genpdf::elements;
+use genpdf::{Alignment, Element};
+use image::{DynamicImage, GenericImageView, RgbImage};
+
+use crate::config::{ImageAlignment, ImageConfig};
+use crate::GenerationError;
+
+impl From<ImageAlignment> for Alignment {
+    fn from(value: ImageAlignment) -> Self {
+        match value {
+            ImageAlignment::Left => Alignment::Left,
+            ImageAlignment::Center => Alignment::Center,
+            ImageAlignment::Right => Alignment::Right,
+        }
+    }
+}
+
+// PDFs can't store an alpha channel with the image, so blend transparent logos onto white paper
+fn remove_alpha(image: DynamicImage) -> DynamicImage {
+    if !image.color().has_alpha() {
+        return image;
+    }
+
+    let rgba = image.to_rgba8();
+    DynamicImage::ImageRgb8(RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let [r, g, b, a] = rgba.get_pixel(x, y).0;
+        let blend = |c: u8| ((c as u16 * a as u16 + 255 * (255 - a as u16)) / 255) as u8;
+        image::Rgb([blend(r), blend(g), blend(b)])
+    }))
+}
+
+// Loads the image scaled to a percentage of the text width
+pub fn image_element(
+    path: &str,
+    width_percent: u8,
+    alignment: Alignment,
+    text_width_mm: f64,
+) -> Result<Box<dyn Element>, GenerationError> {
+    let width_mm = text_width_mm * width_percent.clamp(1, 100) as f64 / 100.0;
+    let image = image::open(path)?;
+    let width_px = image.width();
+    let image = elements::Image::from_dynamic_image(remove_alpha(image))?
+        .with_dpi(width_px as f64 * 25.4 / width_mm)
+        .with_alignment(alignment);
+    Ok(Box::new(image))
+}
+
+pub fn from_config(
+    config: &ImageConfig,
+    text_width_mm: f64,
+) -> Result<Box<dyn Element>, GenerationError> {
+    image_element(
+        &config.path,
+        config.width,
+        config.alignment.into(),
+        text_width_mm,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_image_is_an_error() {
+        let result = image_element("ekki-til.png", 50, Alignment::Center, 100.0);
+        assert!(matches!(result, Err(GenerationError::ImageError(_))));
+    }
+}
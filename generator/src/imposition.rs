@@ -0,0 +1,456 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Imposition {
+    // The pages in reading order, row by row on each side of the sheet
+    Sequential,
+    // Sheets that are folded in the middle of each pair of pages and stapled along the fold
+    Booklet,
+    // Booklets of a few sheets each, folded separately and then sewn or glued together
+    Signatures,
+    // Each tile holds a run of pages through the stack, so after cutting the stack the piles
+    // only have to be put on top of each other
+    CutAndStack,
+}
+
+impl fmt::Display for Imposition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Sequential => "Í röð",
+            Self::Booklet => "Hefti",
+            Self::Signatures => "Arkir",
+            Self::CutAndStack => "Skera og stafla",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Imposition {
+    // Whether the pages are folded apart in pairs
+    pub fn is_folded(&self) -> bool {
+        matches!(self, Self::Booklet | Self::Signatures)
+    }
+}
+
+pub static IMPOSITION_VARIANTS: &[Imposition] = &[
+    Imposition::Sequential,
+    Imposition::Booklet,
+    Imposition::Signatures,
+    Imposition::CutAndStack,
+];
+
+// The edge the printer turns the sheet over on when printing on both sides
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DuplexFlip {
+    LongEdge,
+    ShortEdge,
+    // Only the front of each sheet is printed on
+    OneSided,
+}
+
+impl fmt::Display for DuplexFlip {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::LongEdge => "Langhlið",
+            Self::ShortEdge => "Skammhlið",
+            Self::OneSided => "Einhliða",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+pub static DUPLEX_FLIP_VARIANTS: &[DuplexFlip] = &[
+    DuplexFlip::LongEdge,
+    DuplexFlip::ShortEdge,
+    DuplexFlip::OneSided,
+];
+
+// The tiles on one side of the sheet
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Grid {
+    pub rows: usize,
+    pub columns: usize,
+    pub portrait: bool,
+}
+
+impl Grid {
+    pub fn tiles(&self) -> usize {
+        self.rows * self.columns
+    }
+}
+
+// A mark on the outside of the fold between a tile and the one to the right of it. Each
+// signature's mark is a step further down the spine, so when the signatures are stacked in order
+// the marks form a diagonal line.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SpineMark {
+    pub tile: usize,
+    pub signature: usize,
+    pub signature_count: usize,
+}
+
+// One side of a sheet, with the zero-based page index for each tile in row-major order, or None
+// where the tile is left blank
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SheetSide {
+    pub slots: Vec<Option<usize>>,
+    // Backs are laid out as seen when the sheet is turned over on its left or right edge. When
+    // the printer turns it over on the top edge instead, the whole side has to be upside down.
+    pub rotated: bool,
+    pub back: bool,
+    pub spine_marks: Vec<SpineMark>,
+    // For each tile, how many leaves are folded around it, positive when the spine is to the
+    // right of it. The inner leaves of a thick booklet stick out past the outer ones, so their
+    // pages are moved toward the spine by this many paper thicknesses.
+    pub creep: Vec<isize>,
+}
+
+// The book is padded with blank pages to a multiple of this before it's imposed, so the pages
+// at the end of the book end up at the end
+pub fn pages_per_sheet(imposition: Imposition, grid: Grid, duplex: DuplexFlip) -> usize {
+    match (imposition, duplex) {
+        (Imposition::CutAndStack, DuplexFlip::OneSided) => grid.tiles(),
+        _ => 2 * grid.tiles(),
+    }
+}
+
+// The sides of the sheets in printing order, a front followed by its back
+pub fn impose(
+    imposition: Imposition,
+    page_count: usize,
+    grid: Grid,
+    duplex: DuplexFlip,
+    sheets_per_signature: usize,
+) -> Result<Vec<SheetSide>, String> {
+    match imposition {
+        Imposition::Sequential => Ok(sequential(page_count, grid)),
+        Imposition::Booklet => booklet(page_count, grid, duplex),
+        Imposition::Signatures => signatures(page_count, grid, duplex, sheets_per_signature),
+        Imposition::CutAndStack => Ok(cut_and_stack(page_count, grid, duplex)),
+    }
+}
+
+fn sequential(page_count: usize, grid: Grid) -> Vec<SheetSide> {
+    let sides = page_count.div_ceil(grid.tiles());
+    (0..sides)
+        .map(|side| SheetSide {
+            slots: (side * grid.tiles()..(side + 1) * grid.tiles())
+                .map(|page| (page < page_count).then_some(page))
+                .collect(),
+            rotated: false,
+            back: side % 2 == 1,
+            spine_marks: Vec::new(),
+            creep: vec![0; grid.tiles()],
+        })
+        .collect()
+}
+
+fn backs_rotated(grid: Grid, duplex: DuplexFlip) -> bool {
+    // The long edge is the left and right edge of a portrait sheet
+    match duplex {
+        DuplexFlip::LongEdge => !grid.portrait,
+        DuplexFlip::ShortEdge => grid.portrait,
+        DuplexFlip::OneSided => false,
+    }
+}
+
+// The tile on the back of a sheet that's behind the given tile on the front
+fn back_tile(grid: Grid, tile: usize) -> usize {
+    let (row, column) = (tile / grid.columns, tile % grid.columns);
+    row * grid.columns + grid.columns - 1 - column
+}
+
+fn cut_and_stack(page_count: usize, grid: Grid, duplex: DuplexFlip) -> Vec<SheetSide> {
+    let pages_per_leaf = if duplex == DuplexFlip::OneSided { 1 } else { 2 };
+    let leaf_count = page_count.div_ceil(pages_per_leaf);
+    let sheet_count = leaf_count.div_ceil(grid.tiles());
+    let page = |index: usize| (index < page_count).then_some(index);
+
+    let mut sides = Vec::with_capacity(pages_per_leaf * sheet_count);
+    for sheet in 0..sheet_count {
+        let mut front = vec![None; grid.tiles()];
+        let mut back = vec![None; grid.tiles()];
+        for tile in 0..grid.tiles() {
+            let leaf = tile * sheet_count + sheet;
+            front[tile] = page(pages_per_leaf * leaf);
+            back[back_tile(grid, tile)] = page(2 * leaf + 1);
+        }
+        sides.push(SheetSide {
+            slots: front,
+            rotated: false,
+            back: false,
+            spine_marks: Vec::new(),
+            creep: vec![0; grid.tiles()],
+        });
+        if duplex != DuplexFlip::OneSided {
+            sides.push(SheetSide {
+                slots: back,
+                rotated: backs_rotated(grid, duplex),
+                back: true,
+                spine_marks: Vec::new(),
+                creep: vec![0; grid.tiles()],
+            });
+        }
+    }
+    sides
+}
+
+// Each pair of tiles in a row holds one folded leaf of the booklet. The leaves are spread over
+// the sheets so that after cutting the stack, putting the piles from the first tile on top of
+// the next ones gives the leaves from the outermost to the innermost.
+fn booklet(page_count: usize, grid: Grid, duplex: DuplexFlip) -> Result<Vec<SheetSide>, String> {
+    if !grid.columns.is_multiple_of(2) {
+        return Err(format!(
+            "A booklet needs an even number of pages side by side, not {}",
+            grid.columns
+        ));
+    }
+    if page_count == 0 {
+        return Ok(Vec::new());
+    }
+
+    let pairs_per_row = grid.columns / 2;
+    let leaves_per_side = grid.rows * pairs_per_row;
+    let leaf_count = page_count.div_ceil(4);
+    let sheet_count = leaf_count.div_ceil(leaves_per_side);
+    let padded_count = 4 * leaves_per_side * sheet_count;
+    let page = |index: usize| (index < page_count).then_some(index);
+
+    let mut sides = Vec::with_capacity(2 * sheet_count);
+    for sheet in 0..sheet_count {
+        let mut front = vec![None; grid.tiles()];
+        let mut back = vec![None; grid.tiles()];
+        let mut front_creep = vec![0; grid.tiles()];
+        let mut back_creep = vec![0; grid.tiles()];
+        for row in 0..grid.rows {
+            for pair in 0..pairs_per_row {
+                let leaf = (row * pairs_per_row + pair) * sheet_count + sheet;
+                let outer = 2 * leaf;
+                let inner = padded_count - 1 - 2 * leaf;
+
+                let front_tile = row * grid.columns + 2 * pair;
+                front[front_tile] = page(inner);
+                front[front_tile + 1] = page(outer);
+                front_creep[front_tile] = leaf as isize;
+                front_creep[front_tile + 1] = -(leaf as isize);
+
+                // Turning the sheet over moves the pair to the other side of the row
+                let back_tile = row * grid.columns + 2 * (pairs_per_row - 1 - pair);
+                back[back_tile] = page(outer + 1);
+                back[back_tile + 1] = page(inner - 1);
+                back_creep[back_tile] = leaf as isize;
+                back_creep[back_tile + 1] = -(leaf as isize);
+            }
+        }
+        sides.push(SheetSide {
+            slots: front,
+            rotated: false,
+            back: false,
+            spine_marks: Vec::new(),
+            creep: front_creep,
+        });
+        sides.push(SheetSide {
+            slots: back,
+            rotated: backs_rotated(grid, duplex),
+            back: true,
+            spine_marks: Vec::new(),
+            creep: back_creep,
+        });
+    }
+    Ok(sides)
+}
+
+// Each signature is a booklet of its own, and the last one may have fewer sheets
+fn signatures(
+    page_count: usize,
+    grid: Grid,
+    duplex: DuplexFlip,
+    sheets_per_signature: usize,
+) -> Result<Vec<SheetSide>, String> {
+    let pages_per_signature = 2 * grid.tiles() * sheets_per_signature.max(1);
+    let signature_count = page_count.div_ceil(pages_per_signature);
+
+    let mut sides = Vec::new();
+    for signature in 0..signature_count {
+        let first_page = signature * pages_per_signature;
+        let mut signature_sides = booklet(
+            pages_per_signature.min(page_count - first_page),
+            grid,
+            duplex,
+        )?;
+        for side in &mut signature_sides {
+            for page in side.slots.iter_mut().flatten() {
+                *page += first_page;
+            }
+        }
+        // The outermost leaf is in the first tiles of the signature's first sheet
+        signature_sides[0].spine_marks.push(SpineMark {
+            tile: 0,
+            signature,
+            signature_count,
+        });
+        sides.extend(signature_sides);
+    }
+    Ok(sides)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A5_ON_A4: Grid = Grid {
+        rows: 1,
+        columns: 2,
+        portrait: false,
+    };
+    const A7_ON_A4: Grid = Grid {
+        rows: 2,
+        columns: 4,
+        portrait: false,
+    };
+
+    fn slots(side: &SheetSide) -> Vec<Option<usize>> {
+        side.slots.clone()
+    }
+
+    fn pages(pages: &[usize]) -> Vec<Option<usize>> {
+        pages.iter().map(|p| Some(*p)).collect()
+    }
+
+    #[test]
+    fn test_booklet_eight_pages() {
+        let sides = booklet(8, A5_ON_A4, DuplexFlip::ShortEdge).unwrap();
+        let orders = sides.iter().map(slots).collect::<Vec<_>>();
+        assert_eq!(
+            orders,
+            [
+                pages(&[7, 0]),
+                pages(&[1, 6]),
+                pages(&[5, 2]),
+                pages(&[3, 4])
+            ]
+        );
+        assert!(sides.iter().all(|side| !side.rotated));
+
+        let sides = booklet(8, A5_ON_A4, DuplexFlip::LongEdge).unwrap();
+        let rotated = sides.iter().map(|side| side.rotated).collect::<Vec<_>>();
+        assert_eq!(rotated, [false, true, false, true]);
+    }
+
+    #[test]
+    fn test_booklet_pads_with_blank_pages() {
+        let sides = booklet(1, A5_ON_A4, DuplexFlip::ShortEdge).unwrap();
+        let orders = sides.iter().map(slots).collect::<Vec<_>>();
+        assert_eq!(orders, [vec![None, Some(0)], vec![None, None]]);
+    }
+
+    #[test]
+    fn test_booklet_several_leaves_per_side() {
+        let sides = booklet(16, A7_ON_A4, DuplexFlip::LongEdge).unwrap();
+        assert_eq!(sides.len(), 2);
+        assert_eq!(slots(&sides[0]), pages(&[15, 0, 13, 2, 11, 4, 9, 6]));
+        assert_eq!(slots(&sides[1]), pages(&[3, 12, 1, 14, 7, 8, 5, 10]));
+        assert!(sides[1].rotated);
+    }
+
+    #[test]
+    fn test_booklet_creep() {
+        let sides = booklet(16, A5_ON_A4, DuplexFlip::ShortEdge).unwrap();
+        let creep = sides
+            .iter()
+            .map(|side| side.creep.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            creep,
+            [
+                [0, 0],
+                [0, 0],
+                [1, -1],
+                [1, -1],
+                [2, -2],
+                [2, -2],
+                [3, -3],
+                [3, -3]
+            ]
+        );
+
+        let sides = signatures(16, A5_ON_A4, DuplexFlip::ShortEdge, 2).unwrap();
+        assert_eq!(sides[4].creep, [0, 0]);
+        assert_eq!(sides[7].creep, [1, -1]);
+    }
+
+    #[test]
+    fn test_signatures() {
+        let sides = signatures(10, A5_ON_A4, DuplexFlip::ShortEdge, 2).unwrap();
+        let orders = sides.iter().map(slots).collect::<Vec<_>>();
+        assert_eq!(
+            orders,
+            [
+                pages(&[7, 0]),
+                pages(&[1, 6]),
+                pages(&[5, 2]),
+                pages(&[3, 4]),
+                vec![None, Some(8)],
+                vec![Some(9), None],
+            ]
+        );
+        let marks = sides
+            .iter()
+            .map(|side| side.spine_marks.iter().map(|m| m.signature).collect())
+            .collect::<Vec<Vec<_>>>();
+        assert_eq!(marks, [vec![0], vec![], vec![], vec![], vec![1], vec![]]);
+    }
+
+    #[test]
+    fn test_cut_and_stack() {
+        let grid = Grid {
+            rows: 2,
+            columns: 2,
+            portrait: true,
+        };
+        let sides = cut_and_stack(8, grid, DuplexFlip::OneSided);
+        let orders = sides.iter().map(slots).collect::<Vec<_>>();
+        assert_eq!(orders, [pages(&[0, 2, 4, 6]), pages(&[1, 3, 5, 7])]);
+
+        let sides = cut_and_stack(14, grid, DuplexFlip::LongEdge);
+        let orders = sides.iter().map(slots).collect::<Vec<_>>();
+        assert_eq!(
+            orders,
+            [
+                pages(&[0, 4, 8, 12]),
+                vec![Some(5), Some(1), Some(13), Some(9)],
+                vec![Some(2), Some(6), Some(10), None],
+                vec![Some(7), Some(3), None, Some(11)],
+            ]
+        );
+        assert!(sides.iter().all(|side| !side.rotated));
+    }
+
+    #[test]
+    fn test_booklet_needs_pairs_of_columns() {
+        let grid = Grid {
+            rows: 1,
+            columns: 1,
+            portrait: true,
+        };
+        assert!(booklet(4, grid, DuplexFlip::LongEdge).is_err());
+    }
+
+    #[test]
+    fn test_empty_and_single_page_books() {
+        for imposition in [
+            Imposition::Sequential,
+            Imposition::Booklet,
+            Imposition::Signatures,
+            Imposition::CutAndStack,
+        ] {
+            let sides = impose(imposition, 0, A5_ON_A4, DuplexFlip::ShortEdge, 1).unwrap();
+            assert!(sides.is_empty());
+            let sides = impose(imposition, 1, A5_ON_A4, DuplexFlip::ShortEdge, 1).unwrap();
+            let placed = sides.iter().flat_map(|side| side.slots.iter().flatten());
+            assert_eq!(placed.collect::<Vec<_>>(), [&0]);
+        }
+    }
+}
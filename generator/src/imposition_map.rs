@@ -0,0 +1,248 @@
+use std::fmt::Write as _;
+
+use serde::Serialize;
+
+use crate::imposition::SheetSide;
+use crate::sheet::{Line, Rect, SheetGeometry};
+
+// Room above each side of the sheet in the SVG for saying which side it is
+const CAPTION_HEIGHT_MM: f64 = 10.0;
+const SIDE_GAP_MM: f64 = 10.0;
+
+// Where each page of the book lands on the sheets, to check the imposition before printing.
+// Lengths are in millimeters, with the origin in the bottom left corner of the sheet.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ImpositionMap {
+    pub sheet_width_mm: f64,
+    pub sheet_height_mm: f64,
+    pub sides: Vec<MapSide>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MapSide {
+    // Counted from one, like the sheet numbers in the slug
+    pub sheet: usize,
+    pub back: bool,
+    // Whether the side is printed upside down
+    pub rotated: bool,
+    pub tiles: Vec<MapTile>,
+    pub cuts: Vec<Line>,
+    pub folds: Vec<Line>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MapTile {
+    pub rect: Rect,
+    // The page number, counted from one, or None where the tile is left blank
+    pub page: Option<usize>,
+    pub label: String,
+}
+
+// The labels are for each page of the book, e.g. the songs that start on it
+pub fn imposition_map(
+    sides: &[SheetSide],
+    sheet_size: (f64, f64),
+    copies: &[SheetGeometry],
+    labels: &[String],
+) -> ImpositionMap {
+    let mut sheet = 0;
+    let sides = sides
+        .iter()
+        .map(|side| {
+            if !side.back {
+                sheet += 1;
+            }
+            let mut map_side = MapSide {
+                sheet,
+                back: side.back,
+                rotated: side.rotated,
+                tiles: Vec::new(),
+                cuts: Vec::new(),
+                folds: Vec::new(),
+            };
+            for geometry in copies {
+                for (slot, tile) in side.slots.iter().zip(&geometry.tiles) {
+                    map_side.tiles.push(MapTile {
+                        rect: *tile,
+                        page: slot.map(|index| index + 1),
+                        label: slot
+                            .and_then(|index| labels.get(index))
+                            .cloned()
+                            .unwrap_or_default(),
+                    });
+                }
+                map_side.cuts.extend(geometry.cut_lines());
+                map_side.folds.extend(geometry.fold_lines());
+            }
+            map_side
+        })
+        .collect();
+
+    ImpositionMap {
+        sheet_width_mm: sheet_size.0,
+        sheet_height_mm: sheet_size.1,
+        sides,
+    }
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+impl ImpositionMap {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("The imposition map only has plain values")
+    }
+
+    // The sides of the sheets one below the other, front followed by back
+    pub fn to_svg(&self) -> String {
+        let (width, height) = (self.sheet_width_mm, self.sheet_height_mm);
+        let side_height = CAPTION_HEIGHT_MM + height + SIDE_GAP_MM;
+        let total_height = side_height * self.sides.len() as f64;
+        let sheet_count = self.sides.iter().filter(|side| !side.back).count();
+
+        let mut svg = String::new();
+        // Writing to a String can't fail
+        let _ = writeln!(
+            svg,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}mm" height="{h}mm" viewBox="0 0 {w} {h}" font-family="sans-serif">"#,
+            w = width,
+            h = total_height,
+        );
+        for (i, side) in self.sides.iter().enumerate() {
+            let top = i as f64 * side_height + CAPTION_HEIGHT_MM;
+            // The map's y axis points up, but the SVG's points down
+            let y = |y: f64| top + height - y;
+            let side_name = if side.back { "bakhlið" } else { "framhlið" };
+
+            let _ = writeln!(
+                svg,
+                r#"<text x="0" y="{}" font-size="5">Örk {}/{}, {}</text>"#,
+                top - 3.0,
+                side.sheet,
+                sheet_count,
+                side_name
+            );
+            let _ = writeln!(
+                svg,
+                r##"<rect x="0" y="{}" width="{}" height="{}" fill="#fff" stroke="#000" stroke-width="0.5"/>"##,
+                top, width, height
+            );
+            for tile in &side.tiles {
+                let rect = tile.rect;
+                let fill = if tile.page.is_some() { "#eef" } else { "#eee" };
+                let _ = writeln!(
+                    svg,
+                    r##"<rect x="{}" y="{}" width="{}" height="{}" fill="{}" stroke="#99a" stroke-width="0.3"/>"##,
+                    rect.x,
+                    y(rect.top()),
+                    rect.width,
+                    rect.height,
+                    fill
+                );
+                let Some(page) = tile.page else {
+                    continue;
+                };
+                let center_x = rect.x + rect.width / 2.0;
+                let center_y = y(rect.y + rect.height / 2.0);
+                let font_size = (rect.height / 4.0).min(rect.width / 3.0).min(20.0);
+                let _ = writeln!(
+                    svg,
+                    r#"<text x="{}" y="{}" font-size="{}" text-anchor="middle">{}</text>"#,
+                    center_x, center_y, font_size, page
+                );
+                if !tile.label.is_empty() {
+                    let _ = writeln!(
+                        svg,
+                        r#"<text x="{}" y="{}" font-size="{}" text-anchor="middle">{}</text>"#,
+                        center_x,
+                        center_y + font_size / 2.0,
+                        font_size / 4.0,
+                        escape_xml(&tile.label)
+                    );
+                }
+            }
+            for (lines, style) in [
+                (&side.cuts, r##"stroke="#c00" stroke-width="0.3""##),
+                (
+                    &side.folds,
+                    r##"stroke="#00c" stroke-width="0.3" stroke-dasharray="2 1""##,
+                ),
+            ] {
+                for line in lines {
+                    let _ = writeln!(
+                        svg,
+                        r#"<line x1="{}" y1="{}" x2="{}" y2="{}" {}/>"#,
+                        line.from.0,
+                        y(line.from.1),
+                        line.to.0,
+                        y(line.to.1),
+                        style
+                    );
+                }
+            }
+        }
+        svg.push_str("</svg>\n");
+        svg
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::imposition::{impose, DuplexFlip, Grid, Imposition};
+    use crate::sheet::MarkSettings;
+
+    #[test]
+    fn test_booklet_map() {
+        let grid = Grid {
+            rows: 1,
+            columns: 2,
+            portrait: false,
+        };
+        let sides = impose(Imposition::Booklet, 8, grid, DuplexFlip::ShortEdge, 1).unwrap();
+        let marks = MarkSettings {
+            crop_marks: false,
+            bleed_mm: 0.0,
+            folded: true,
+            creep_mm: 0.0,
+        };
+        let area = Rect {
+            x: 0.0,
+            y: 0.0,
+            width: 297.0,
+            height: 210.0,
+        };
+        let geometry = SheetGeometry::new(area, grid, (148.5, 210.0), marks, 0.0);
+        let labels = (1..=8)
+            .map(|page| format!("Lag <{}>", page))
+            .collect::<Vec<_>>();
+        let map = imposition_map(&sides, (297.0, 210.0), &[geometry], &labels);
+
+        let pages = map
+            .sides
+            .iter()
+            .map(|side| side.tiles.iter().map(|tile| tile.page).collect())
+            .collect::<Vec<Vec<_>>>();
+        assert_eq!(
+            pages,
+            [
+                [Some(8), Some(1)],
+                [Some(2), Some(7)],
+                [Some(6), Some(3)],
+                [Some(4), Some(5)]
+            ]
+        );
+        let sheets = map.sides.iter().map(|side| side.sheet).collect::<Vec<_>>();
+        assert_eq!(sheets, [1, 1, 2, 2]);
+        assert_eq!(map.sides[0].tiles[1].label, "Lag <1>");
+        assert_eq!(map.sides[0].folds.len(), 1);
+
+        let svg = map.to_svg();
+        assert!(svg.contains("Lag &lt;1&gt;"));
+        assert!(svg.contains("Örk 2/2, bakhlið"));
+    }
+}
@@ -1,99 +1,425 @@
+pub mod authors;
+pub mod cache;
 pub mod config;
+pub mod doctor;
+pub mod elements;
+mod error;
 pub mod fonts;
 pub mod gen_pdfs;
+pub mod images;
+pub mod imposition;
+pub mod imposition_map;
+pub mod markdown;
+pub mod native_tile;
+pub mod preview;
+pub mod progress;
+pub mod sheet;
 pub mod tile;
 
-use std::{collections::HashMap, fmt::Display, fs};
+use std::{collections::HashMap, fs};
 
-use fonts::FontError;
-use pdfium_render::prelude::{Pdfium, PdfiumError};
+pub use error::GenerationError;
+use pdfium_render::prelude::{PdfDocument, PdfPageIndex, Pdfium};
+use progress::{Progress, ProgressReporter, SongCounter};
 use wasm_bindgen::prelude::*;
 
-#[derive(Debug)]
-pub enum GenerationError {
-    PdfiumError(PdfiumError),
-    FontError(FontError),
+fn generate_page(
+    font: &gen_pdfs::Font,
+    config: &config::BookConfig,
+    page: &config::Page,
+    cache: &cache::RenderCache,
+) -> Result<tile::PdfPart, GenerationError> {
+    match page {
+        config::Page::ExternalPdf(pdf) => Ok(tile::PdfPart::File {
+            path: pdf.path.clone(),
+            pages: pdf.page_indices().map_err(GenerationError::ConfigError)?,
+        }),
+        page => Ok(gen_pdfs::generate_cached_page(font, config, page, cache)?.into()),
+    }
+}
+
+// A part of the book that's laid out on its own, so the parts can be laid out at the same time
+enum BookPart<'a> {
+    Page(&'a config::Page),
+    Songs(gen_pdfs::SongSection<'a>),
+}
+
+// With the parallel feature the items are spread over all cores, and the results are in the same
+// order as the items either way
+#[cfg(feature = "parallel")]
+fn map_in_order<T: Sync, R: Send>(items: &[T], f: impl Fn(&T) -> R + Sync + Send) -> Vec<R> {
+    use rayon::prelude::*;
+    items.par_iter().map(f).collect()
 }
 
-impl From<PdfiumError> for GenerationError {
-    fn from(value: PdfiumError) -> Self {
-        Self::PdfiumError(value)
+#[cfg(not(feature = "parallel"))]
+fn map_in_order<T, R>(items: &[T], f: impl Fn(&T) -> R) -> Vec<R> {
+    items.iter().map(f).collect()
+}
+
+// The pages are laid out again only where something they're laid out from has changed since the
+// last book was made
+pub fn generate_book_pdfs(
+    config: &config::BookConfig,
+    progress: &ProgressReporter,
+) -> Result<Vec<gen_pdfs::LaidOutPart>, GenerationError> {
+    let font = gen_pdfs::load_font(&config.preferred_font)?;
+    progress.report(Progress::FontLoaded)?;
+    let cache = cache::RenderCache::new(RENDER_CACHE_DIR);
+    let numbers = config
+        .numbered_songs()
+        .into_iter()
+        .map(|(num, _)| num)
+        .collect::<Vec<_>>();
+    let songs = SongCounter::new(progress, numbers.len());
+
+    let parts = config
+        .front_pages
+        .iter()
+        .map(BookPart::Page)
+        .chain(
+            gen_pdfs::song_sections(config, &numbers)
+                .into_iter()
+                .map(BookPart::Songs),
+        )
+        .chain(config.back_pages.iter().map(BookPart::Page))
+        .collect::<Vec<_>>();
+    let pdfs = map_in_order(&parts, |part| match part {
+        BookPart::Page(page) => Ok(vec![generate_page(&font, config, page, &cache)?.into()]),
+        BookPart::Songs(section) => {
+            gen_pdfs::generate_songs(&font, config, section, &cache, &songs)
+        }
+    })
+    .into_iter()
+    .collect::<Result<Vec<_>, GenerationError>>()?;
+
+    if let Err(e) = cache.prune() {
+        println!("Failed to clean up the render cache: {}", e);
     }
+    return Ok(pdfs.into_iter().flatten().collect());
 }
 
-impl From<FontError> for GenerationError {
-    fn from(value: FontError) -> Self {
-        Self::FontError(value)
+pub const PDFIUM_PATH_VAR: &str = "SKATA_PDFIUM_PATH";
+pub const SONGS_DIR: &str = "./songs/";
+pub const RENDER_CACHE_DIR: &str = "./render-cache/";
+
+// Pdfium is looked for where the settings say, then where the environment variable says, then
+// next to the program and finally wherever the system keeps its libraries. The paths can be the
+// library itself or the folder it's in. Returns where it was found.
+#[cfg_attr(target_family = "wasm", allow(unused_variables))]
+pub fn bind_pdfium(config: &config::BookConfig) -> Result<(Pdfium, String), GenerationError> {
+    let mut tried = Vec::new();
+
+    #[cfg(not(target_family = "wasm"))]
+    {
+        let paths = config
+            .pdfium_path
+            .iter()
+            .cloned()
+            .chain(std::env::var(PDFIUM_PATH_VAR))
+            .chain(["./".to_owned()]);
+        for path in paths {
+            let path = std::path::PathBuf::from(path);
+            let library = if path.is_dir() {
+                Pdfium::pdfium_platform_library_name_at_path(&path)
+            } else {
+                path
+            };
+            match Pdfium::bind_to_library(&library) {
+                Ok(bindings) => return Ok((Pdfium::new(bindings), library.display().to_string())),
+                Err(e) => tried.push(format!("{}: {}", library.display(), e)),
+            }
+        }
+    }
+
+    match Pdfium::bind_to_system_library() {
+        Ok(bindings) => Ok((Pdfium::new(bindings), "system library".to_owned())),
+        Err(e) => {
+            tried.push(format!("system library: {}", e));
+            Err(GenerationError::PdfiumNotFound(tried))
+        }
     }
 }
 
-impl Display for GenerationError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "GenerationError::")?;
+// The merged pages of the book, in the document of whichever backend merged them
+enum BookPages<'a> {
+    Pdfium(&'a Pdfium, PdfDocument<'a>),
+    Native(native_tile::NativeBook),
+}
+
+impl<'a> BookPages<'a> {
+    // Also returns the page each part starts on
+    fn merge(
+        pdfium: Option<&'a Pdfium>,
+        pdfs: Vec<tile::PdfPart>,
+        page_size_mm: (f64, f64),
+    ) -> Result<(BookPages<'a>, Vec<usize>), GenerationError> {
+        Ok(match pdfium {
+            Some(pdfium) => {
+                let (doc, starts) = tile::merge_pdfs(pdfium, pdfs, tile::paper_size(page_size_mm))?;
+                (BookPages::Pdfium(pdfium, doc), starts)
+            }
+            None => {
+                let (book, starts) = native_tile::merge_pdfs(pdfs, page_size_mm)?;
+                (BookPages::Native(book), starts)
+            }
+        })
+    }
+
+    fn page_count(&self) -> usize {
         match self {
-            Self::PdfiumError(e) => write!(f, "PdfiumError({})", e),
-            Self::FontError(e) => write!(f, "FontError({})", e),
+            BookPages::Pdfium(_, doc) => doc.pages().len() as usize,
+            BookPages::Native(book) => book.page_count(),
         }
     }
-}
 
-pub fn generate_book_pdfs(config: &config::BookConfig) -> Result<Vec<Vec<u8>>, FontError> {
-    let font = gen_pdfs::load_font(&config.preferred_font)?;
-    let mut pdfs = Vec::with_capacity(
-        config.front_pages.len()
-            + config.back_pages.len()
-            + (if config.songs.is_empty() { 0 } else { 1 }),
-    );
+    fn add_blank_pages(
+        &mut self,
+        count: usize,
+        page_size_mm: (f64, f64),
+    ) -> Result<(), GenerationError> {
+        match self {
+            BookPages::Pdfium(_, doc) => {
+                tile::add_blank_pages(doc, count as u16, tile::paper_size(page_size_mm))?
+            }
+            BookPages::Native(book) => book.add_blank_pages(count),
+        }
+        Ok(())
+    }
 
-    for page in &config.front_pages {
-        pdfs.push(gen_pdfs::generate_page(&font, &config.songs, page));
+    fn append(&mut self, other: BookPages<'a>) -> Result<(), GenerationError> {
+        match (self, other) {
+            (BookPages::Pdfium(_, doc), BookPages::Pdfium(_, other)) => {
+                doc.pages_mut().append(&other)?
+            }
+            (BookPages::Native(book), BookPages::Native(other)) => book.append(other),
+            _ => unreachable!("Both parts of the book are merged by the same backend"),
+        }
+        Ok(())
     }
+}
+
+// The pages of the book in order, and where they go on the sides of the sheets
+struct ImposedBook<'a> {
+    pages: BookPages<'a>,
+    sides: Vec<imposition::SheetSide>,
+    sheet_size: (f64, f64),
+    copies: Vec<sheet::SheetGeometry>,
+    // The songs whose titles are on each page
+    labels: Vec<String>,
+}
 
-    if !config.songs.is_empty() {
-        let songs = gen_pdfs::generate_songs(&font, &config.songs);
-        pdfs.push(songs);
+// Pdfium is only bound when it's chosen, so the built-in backend works without it
+fn impose_book<'a>(
+    pdfium: Option<&'a Pdfium>,
+    config: &config::BookConfig,
+    progress: &ProgressReporter,
+) -> Result<ImposedBook<'a>, GenerationError> {
+    config.validate()?;
+    let (mut pdfs, song_starts): (Vec<_>, Vec<_>) = generate_book_pdfs(config, progress)?
+        .into_iter()
+        .map(|part| (part.pdf, part.info.song_starts))
+        .unzip();
+    progress.report(Progress::Merging)?;
+
+    // Padding goes between the songs and the back pages, so the back cover stays at the back
+    let page_size = config.tiled_page_size.size_mm();
+    let back_pdfs = pdfs.split_off(pdfs.len() - config.back_pages.len());
+    let (mut pages, part_starts) = BookPages::merge(pdfium, pdfs, page_size)?;
+    let (back_pages, _) = BookPages::merge(pdfium, back_pdfs, page_size)?;
+    let layout = sheet::layout_sheet(
+        config.sheet_size.size_mm(),
+        page_size,
+        config.grid,
+        config.imposition.is_folded(),
+        config.copies_per_sheet as usize,
+    );
+    let grid = layout.grid;
+    if config.pad_to_full_sheets || config.imposition != imposition::Imposition::Sequential {
+        let pages_per_sheet =
+            imposition::pages_per_sheet(config.imposition, grid, config.duplex_flip);
+        let page_count = pages.page_count() + back_pages.page_count();
+        let missing_pages = (pages_per_sheet - page_count % pages_per_sheet) % pages_per_sheet;
+        pages.add_blank_pages(missing_pages, page_size)?;
     }
+    pages.append(back_pages)?;
+
+    progress.report(Progress::Imposing)?;
+    let sides = imposition::impose(
+        config.imposition,
+        pages.page_count(),
+        grid,
+        config.duplex_flip,
+        config.sheets_per_signature as usize,
+    )
+    .map_err(GenerationError::ConfigError)?;
+    let marks = sheet::MarkSettings {
+        crop_marks: config.crop_marks,
+        bleed_mm: config.bleed_mm as f64,
+        folded: config.imposition.is_folded(),
+        creep_mm: config.creep_per_leaf_um as f64 / 1000.0,
+    };
+    let copies = layout
+        .copies
+        .iter()
+        .map(|area| {
+            sheet::SheetGeometry::new(*area, grid, page_size, marks, config.padding as f64 / 100.0)
+        })
+        .collect::<Vec<_>>();
+    let labels = page_labels(pages.page_count(), &part_starts, &song_starts);
+    Ok(ImposedBook {
+        pages,
+        sides,
+        sheet_size: layout.size,
+        copies,
+        labels,
+    })
+}
 
-    for page in config.back_pages.iter() {
-        pdfs.push(gen_pdfs::generate_page(&font, &config.songs, page));
+fn bind_backend(config: &config::BookConfig) -> Result<Option<Pdfium>, GenerationError> {
+    match config.pdf_backend {
+        config::PdfBackend::Pdfium => Ok(Some(bind_pdfium(config)?.0)),
+        config::PdfBackend::Native => Ok(None),
     }
+}
 
-    return Ok(pdfs);
+// The imposed sheets, and which side of which sheet each page of the PDF is
+fn generate_sheets(
+    pdfium: Option<&Pdfium>,
+    config: &config::BookConfig,
+    progress: &ProgressReporter,
+) -> Result<(Vec<u8>, Vec<imposition::SheetSide>), GenerationError> {
+    let book = impose_book(pdfium, config, progress)?;
+    progress.report(Progress::Saving)?;
+    let pdf = match book.pages {
+        BookPages::Pdfium(pdfium, doc) => {
+            let tiled_doc = tile::tile_pages(
+                pdfium,
+                &doc,
+                &book.sides,
+                book.sheet_size,
+                &book.copies,
+                config.add_separator,
+                config.title(),
+            )?;
+            tiled_doc.save_to_bytes()?
+        }
+        BookPages::Native(pages) => native_tile::tile_pages(
+            &pages,
+            &book.sides,
+            book.sheet_size,
+            &book.copies,
+            config.add_separator,
+            config.title(),
+        )?,
+    };
+    Ok((pdf, book.sides))
 }
 
 pub fn generate_book_pdf(config: &config::BookConfig) -> Result<Vec<u8>, GenerationError> {
-    let pdfs = generate_book_pdfs(&config)?;
+    generate_book_pdf_with_progress(config, &ProgressReporter::silent())
+}
 
-    #[cfg(target_family = "wasm")]
-    let pdfium = Pdfium::new(Pdfium::bind_to_system_library()?);
-    #[cfg(not(target_family = "wasm"))]
-    let pdfium = Pdfium::new(
-        Pdfium::bind_to_library(Pdfium::pdfium_platform_library_name_at_path("./"))
-            .or_else(|_| Pdfium::bind_to_system_library())?,
-    );
+// Reports each step as it starts, and stops with GenerationError::Cancelled when asked to
+pub fn generate_book_pdf_with_progress(
+    config: &config::BookConfig,
+    progress: &ProgressReporter,
+) -> Result<Vec<u8>, GenerationError> {
+    let pdfium = bind_backend(config)?;
+    Ok(generate_sheets(pdfium.as_ref(), config, progress)?.0)
+}
+
+// Renders each side of the imposed sheets, as made by the chosen backend. Rendering always needs
+// Pdfium.
+pub fn preview_sheets(
+    config: &config::BookConfig,
+    dpi: f32,
+) -> Result<Vec<preview::SheetPreview>, GenerationError> {
+    let (pdfium, _) = bind_pdfium(config)?;
+    let backend = (config.pdf_backend == config::PdfBackend::Pdfium).then_some(&pdfium);
+    let (pdf, sides) = generate_sheets(backend, config, &ProgressReporter::silent())?;
+    let doc = pdfium.load_pdf_from_byte_vec(pdf, None)?;
+
+    let mut previews = Vec::with_capacity(sides.len());
+    let mut sheet = 0;
+    for (page, side) in doc.pages().iter().zip(&sides) {
+        if !side.back {
+            sheet += 1;
+        }
+        previews.push(preview::SheetPreview {
+            sheet,
+            back: side.back,
+            png: preview::render_png(&page, dpi)?,
+        });
+    }
+    Ok(previews)
+}
 
-    let mut pdfium_doc = tile::merge_pdfs(&pdfium, pdfs)?;
-    if config.reorder_pages {
-        pdfium_doc = tile::mix_first_and_last(&pdfium, &pdfium_doc)?;
+// Renders pages of the book before it's imposed, counted from zero, including the blank pages
+// added to fill the sheets. The book is made once for all of them, so ask for every page that's
+// needed at once.
+pub fn preview_pages(
+    config: &config::BookConfig,
+    pages: &[usize],
+    dpi: f32,
+) -> Result<Vec<Vec<u8>>, GenerationError> {
+    let (pdfium, _) = bind_pdfium(config)?;
+    let book = impose_book(Some(&pdfium), config, &ProgressReporter::silent())?;
+    let BookPages::Pdfium(_, doc) = &book.pages else {
+        unreachable!("The pages are merged with Pdfium when it's given");
+    };
+    let page_count = doc.pages().len() as usize;
+    if let Some(page) = pages.iter().find(|page| **page >= page_count) {
+        return Err(GenerationError::ConfigError(format!(
+            "Page {} was asked for, but the book only has {} pages",
+            page, page_count
+        )));
     }
-    let tiled_doc = tile::tile_pages(
-        pdfium_doc.pages(),
-        1.0 - ((config.padding as f32) / 100.0),
-        config.add_separator,
-        config.tiled_page_size,
-    )?;
-    return Ok(tiled_doc.save_to_bytes()?);
+    pages
+        .iter()
+        .map(|page| preview::render_png(&doc.pages().get(*page as PdfPageIndex)?, dpi))
+        .collect()
+}
+
+// Labels each page with the songs whose titles are on it, from where the songs were laid out
+fn page_labels(
+    page_count: usize,
+    part_starts: &[usize],
+    song_starts: &[Vec<gen_pdfs::SongStart>],
+) -> Vec<String> {
+    let mut labels = vec![Vec::new(); page_count];
+    for (part_start, songs) in part_starts.iter().zip(song_starts) {
+        for song in songs {
+            if let Some(label) = labels.get_mut(part_start + song.page) {
+                label.push(song.label.as_str());
+            }
+        }
+    }
+    labels.into_iter().map(|label| label.join(", ")).collect()
+}
+
+// Which page goes where on each side of the sheets, without making the sheets
+pub fn generate_imposition_map(
+    config: &config::BookConfig,
+) -> Result<imposition_map::ImpositionMap, GenerationError> {
+    let pdfium = bind_backend(config)?;
+    let book = impose_book(pdfium.as_ref(), config, &ProgressReporter::silent())?;
+    Ok(imposition_map::imposition_map(
+        &book.sides,
+        book.sheet_size,
+        &book.copies,
+        &book.labels,
+    ))
 }
 
 #[wasm_bindgen]
-pub fn generate_book_pdf_wasm(config_json: String) -> Vec<u8> {
-    let config: config::BookConfig = serde_json::from_str(&config_json).unwrap();
-    return generate_book_pdf(&config).unwrap();
+pub fn generate_book_pdf_wasm(config_json: String) -> Result<Vec<u8>, JsError> {
+    let config: config::BookConfig = serde_json::from_str(&config_json)?;
+    return Ok(generate_book_pdf(&config)?);
 }
 
-pub fn load_song(title: &str) -> Result<config::Song, String> {
-    let songs =
-        fs::read_dir("./songs/").map_err(|e| format!("Failed to read songs directory: {}", e))?;
+pub fn load_song(title: &str) -> Result<config::Song, GenerationError> {
+    let songs = fs::read_dir(SONGS_DIR)?;
     for res in songs {
         let path = match res {
             Ok(song) => song.path(),
@@ -109,18 +435,27 @@ pub fn load_song(title: &str) -> Result<config::Song, String> {
             .unwrap_or("");
 
         if name == title {
-            let body = fs::read_to_string(path.clone()).unwrap();
+            let body = fs::read_to_string(&path)?;
             return parse_song_body(name.to_owned(), &body);
         }
     }
 
-    Err("Song not found".to_owned())
+    Err(GenerationError::SongError(format!(
+        "Song {} not found",
+        title
+    )))
 }
 
-pub fn parse_song_body(title: impl ToString, body: &str) -> Result<config::Song, String> {
+pub fn parse_song_body(title: impl ToString, body: &str) -> Result<config::Song, GenerationError> {
     let title = title.to_string();
-    let tag_err =
-        |i: usize, msg: &str| Err(format!("{} in line {} of song {}", msg, i + 1, &title));
+    let tag_err = |i: usize, msg: &str| {
+        Err(GenerationError::SongError(format!(
+            "{} in line {} of song {}",
+            msg,
+            i + 1,
+            &title
+        )))
+    };
     let mut verses = Vec::new();
     let mut current_verse = String::new();
     let mut tags = HashMap::new();
@@ -172,10 +507,17 @@ pub fn parse_song_body(title: impl ToString, body: &str) -> Result<config::Song,
         verses.push(current_verse);
     }
 
+    // The number of columns can be set per song with a tag
+    let columns = tags
+        .get("dálkar")
+        .and_then(|values| values.first())
+        .and_then(|value| value.parse().ok());
+
     Ok(config::Song {
         title,
         body: verses,
         tags,
+        columns,
     })
 }
 
@@ -206,12 +548,15 @@ hann var það augnayndi,
 sem elska svangir menn.",
     ];
 
-    fn default_parse_song(extra_body: &str) -> (Result<config::Song, String>, config::Song) {
+    fn default_parse_song(
+        extra_body: &str,
+    ) -> (Result<config::Song, GenerationError>, config::Song) {
         let parsed_song = parse_song_body("Aa", &(SONG_BODY.to_owned() + extra_body));
         let expected_song = config::Song {
             title: "Aa".to_owned(),
             body: PARSED_SONG_BODY.iter().map(|v| (*v).to_owned()).collect(),
             tags: HashMap::new(),
+            columns: None,
         };
         (parsed_song, expected_song)
     }
@@ -219,7 +564,7 @@ sem elska svangir menn.",
     #[test]
     fn test_parse_song_body_no_tags() {
         let (parsed, expected) = default_parse_song("");
-        assert_eq!(parsed, Ok(expected));
+        assert_eq!(parsed.unwrap(), expected);
     }
 
     #[test]
@@ -237,4 +582,29 @@ sem elska svangir menn.",
                 .collect()
         );
     }
+
+    #[test]
+    fn test_parse_song_body_columns_tag() {
+        let (parsed, _) = default_parse_song("----\nDálkar: 2");
+        assert_eq!(parsed.unwrap().columns, Some(2));
+    }
+
+    #[test]
+    fn test_page_labels_from_song_starts() {
+        let song = |page, label: &str| gen_pdfs::SongStart {
+            page,
+            label: label.to_owned(),
+        };
+        // A front page, then two parts of songs, the second starting after a blank page
+        let labels = page_labels(
+            7,
+            &[0, 1, 4],
+            &[
+                vec![],
+                vec![song(0, "1. Aa"), song(0, "2. Bb"), song(2, "3. Cc")],
+                vec![song(1, "4. Dd")],
+            ],
+        );
+        assert_eq!(labels, ["", "1. Aa, 2. Bb", "", "3. Cc", "", "4. Dd", ""]);
+    }
 }
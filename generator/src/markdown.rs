@@ -0,0 +1,323 @@
+use genpdf::elements::{self, Paragraph};
+use genpdf::style::{Color, Style, StyledString};
+use genpdf::{Alignment, Element as _, Margins};
+use pulldown_cmark::{Event, HeadingLevel, Options, Parser, Tag};
+
+use crate::{images, GenerationError};
+
+const LINK_COLOR: Color = Color::Rgb(0, 0, 180);
+
+pub fn render(
+    markdown: &str,
+    font_size: u8,
+    text_width_mm: f64,
+) -> Result<elements::LinearLayout, GenerationError> {
+    let mut renderer = Renderer {
+        events: Parser::new_ext(markdown, Options::ENABLE_TABLES),
+        font_size,
+        text_width_mm,
+        image_error: None,
+    };
+    let layout = renderer.blocks();
+    match renderer.image_error {
+        Some(e) => Err(e),
+        None => Ok(layout),
+    }
+}
+
+// The images the markdown shows, which the rendered page depends on as well as the text
+pub fn image_paths(markdown: &str) -> Vec<String> {
+    Parser::new_ext(markdown, Options::ENABLE_TABLES)
+        .filter_map(|event| match event {
+            Event::Start(Tag::Image(_, url, _)) => Some(url.to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn is_inline(tag: &Tag) -> bool {
+    matches!(
+        tag,
+        Tag::Emphasis | Tag::Strong | Tag::Strikethrough | Tag::Link(..) | Tag::Image(..)
+    )
+}
+
+fn to_alignment(alignment: Option<&pulldown_cmark::Alignment>) -> Alignment {
+    match alignment {
+        Some(pulldown_cmark::Alignment::Center) => Alignment::Center,
+        Some(pulldown_cmark::Alignment::Right) => Alignment::Right,
+        _ => Alignment::Left,
+    }
+}
+
+// Collects inline text into a paragraph, keeping track of the emphasis and links it's nested in
+struct InlineText {
+    paragraph: Paragraph,
+    styles: Vec<Style>,
+    links: Vec<(String, String)>,
+    is_empty: bool,
+}
+
+impl InlineText {
+    fn new(style: Style) -> InlineText {
+        InlineText {
+            paragraph: Paragraph::default(),
+            styles: vec![style],
+            links: Vec::new(),
+            is_empty: true,
+        }
+    }
+
+    fn style(&self) -> Style {
+        self.styles.last().copied().unwrap_or_default()
+    }
+
+    fn push_str(&mut self, s: &str) {
+        self.paragraph.push(StyledString::new(s, self.style()));
+        for (_, text) in &mut self.links {
+            text.push_str(s);
+        }
+        self.is_empty = false;
+    }
+
+    fn push(&mut self, event: Event) {
+        match event {
+            Event::Start(Tag::Emphasis) => self.styles.push(self.style().italic()),
+            Event::Start(Tag::Strong) => self.styles.push(self.style().bold()),
+            Event::Start(Tag::Link(_, url, _)) => {
+                self.styles.push(self.style().with_color(LINK_COLOR));
+                self.links.push((url.to_string(), String::new()));
+            }
+            Event::End(Tag::Emphasis | Tag::Strong) => {
+                self.styles.pop();
+            }
+            Event::End(Tag::Link(..)) => {
+                self.styles.pop();
+                // Links can't be clicked on paper, so print where they lead unless the text
+                // already says it
+                if let Some((url, text)) = self.links.pop() {
+                    let target = url.trim_start_matches("mailto:").trim_start_matches("tel:");
+                    if target != text.trim() {
+                        self.push_str(&format!(" ({})", target));
+                    }
+                }
+            }
+            Event::Text(text) | Event::Code(text) => self.push_str(&text),
+            Event::SoftBreak | Event::HardBreak => self.push_str(" "),
+            _ => {}
+        }
+    }
+}
+
+struct Renderer<I> {
+    events: I,
+    font_size: u8,
+    text_width_mm: f64,
+    // The first image that couldn't be loaded, which fails the whole page
+    image_error: Option<GenerationError>,
+}
+
+impl<'a, I: Iterator<Item = Event<'a>>> Renderer<I> {
+    fn body_style(&self) -> Style {
+        Style::new().with_font_size(self.font_size)
+    }
+
+    // Renders block elements until the block they're contained in ends
+    fn blocks(&mut self) -> elements::LinearLayout {
+        let mut layout = elements::LinearLayout::vertical();
+        let mut pending_text: Option<InlineText> = None;
+        let mut is_first = true;
+
+        while let Some(event) = self.events.next() {
+            let is_block = match &event {
+                Event::Start(tag) | Event::End(tag) => !is_inline(tag),
+                Event::Rule => true,
+                _ => false,
+            };
+            if !is_block {
+                pending_text
+                    .get_or_insert_with(|| InlineText::new(self.body_style()))
+                    .push(event);
+                continue;
+            }
+
+            // Text directly inside a block, e.g. in a tight list, becomes its own paragraph
+            if let Some(text) = pending_text.take() {
+                if !text.is_empty {
+                    layout.push(text.paragraph);
+                    is_first = false;
+                }
+            }
+            if let Event::End(_) = event {
+                break;
+            }
+
+            if !is_first {
+                layout.push(elements::Break::new(0.5));
+            }
+            is_first = false;
+            match event {
+                Event::Start(Tag::Paragraph) => layout.push(self.paragraph()),
+                Event::Start(Tag::Heading(level, ..)) => {
+                    let font_size = match level {
+                        // Saturating, since the body font size comes from the settings
+                        HeadingLevel::H1 => self.font_size.saturating_add(self.font_size / 2),
+                        HeadingLevel::H2 => self.font_size.saturating_add(self.font_size / 4),
+                        _ => self.font_size,
+                    };
+                    layout.push(self.inlines(self.body_style().bold().with_font_size(font_size)));
+                }
+                Event::Start(Tag::BlockQuote) => layout.push(
+                    self.blocks()
+                        .padded(Margins::trbl(0, 0, 0, 8))
+                        .styled(Style::new().italic()),
+                ),
+                Event::Start(Tag::CodeBlock(_)) => layout.push(self.code_block()),
+                Event::Start(Tag::List(start)) => layout.push(self.list(start)),
+                Event::Start(Tag::Table(alignments)) => layout.push(self.table(alignments)),
+                Event::Start(_) => layout.push(self.blocks()),
+                _ => {}
+            }
+        }
+
+        if let Some(text) = pending_text {
+            layout.push(text.paragraph);
+        }
+        layout
+    }
+
+    // Like inlines, but splits the paragraph around any images in it
+    fn paragraph(&mut self) -> elements::LinearLayout {
+        let mut layout = elements::LinearLayout::vertical();
+        let mut text = InlineText::new(self.body_style());
+        while let Some(event) = self.events.next() {
+            match event {
+                Event::Start(Tag::Image(_, url, title)) => {
+                    if !text.is_empty {
+                        layout.push(text.paragraph);
+                    }
+                    text = InlineText::new(self.body_style());
+
+                    // Skip the alt text
+                    for event in self.events.by_ref() {
+                        if let Event::End(Tag::Image(..)) = event {
+                            break;
+                        }
+                    }
+
+                    // The image title can set the width, e.g. ![logo](logo.png "50%")
+                    let width = title.trim().trim_end_matches('%').parse().unwrap_or(100);
+                    match images::image_element(&url, width, Alignment::Center, self.text_width_mm)
+                    {
+                        Ok(image) => layout.push(image),
+                        Err(e) if self.image_error.is_none() => self.image_error = Some(e),
+                        Err(_) => {}
+                    }
+                }
+                Event::End(tag) if !is_inline(&tag) => break,
+                event => text.push(event),
+            }
+        }
+        if !text.is_empty {
+            layout.push(text.paragraph);
+        }
+        layout
+    }
+
+    // Renders inline elements until the paragraph, heading or table cell they're in ends
+    fn inlines(&mut self, style: Style) -> Paragraph {
+        let mut text = InlineText::new(style);
+        for event in self.events.by_ref() {
+            match event {
+                Event::End(tag) if !is_inline(&tag) => break,
+                event => text.push(event),
+            }
+        }
+        text.paragraph
+    }
+
+    fn code_block(&mut self) -> elements::LinearLayout {
+        let mut layout = elements::LinearLayout::vertical();
+        let style = self.body_style();
+        for event in self.events.by_ref() {
+            match event {
+                Event::Text(text) => {
+                    for line in text.lines() {
+                        layout.push(elements::Paragraph::new(line).styled(style));
+                    }
+                }
+                Event::End(_) => break,
+                _ => {}
+            }
+        }
+        layout
+    }
+
+    fn list(&mut self, start: Option<u64>) -> Box<dyn genpdf::Element> {
+        let mut ordered = start.map(|start| elements::OrderedList::with_start(start as usize));
+        let mut unordered = elements::UnorderedList::new();
+        while let Some(event) = self.events.next() {
+            match event {
+                Event::Start(Tag::Item) => {
+                    let item = self.blocks();
+                    match &mut ordered {
+                        Some(list) => list.push(item),
+                        None => unordered.push(item),
+                    }
+                }
+                Event::End(_) => break,
+                _ => {}
+            }
+        }
+        match ordered {
+            Some(list) => Box::new(list),
+            None => Box::new(unordered),
+        }
+    }
+
+    fn table(&mut self, alignments: Vec<pulldown_cmark::Alignment>) -> elements::TableLayout {
+        let columns = alignments.len().max(1);
+        let mut table = elements::TableLayout::new(vec![1; columns]);
+        table.set_cell_decorator(elements::FrameCellDecorator::new(true, true, false));
+
+        let mut cells = Vec::with_capacity(columns);
+        let mut is_head = false;
+        while let Some(event) = self.events.next() {
+            match event {
+                Event::Start(Tag::TableHead) => is_head = true,
+                Event::Start(Tag::TableCell) => {
+                    let style = if is_head {
+                        self.body_style().bold()
+                    } else {
+                        self.body_style()
+                    };
+                    let alignment = to_alignment(alignments.get(cells.len()));
+                    cells.push(self.inlines(style).aligned(alignment));
+                }
+                Event::End(Tag::TableHead | Tag::TableRow) => {
+                    is_head = false;
+                    cells.resize_with(columns, Paragraph::default);
+                    let mut row = table.row();
+                    for cell in cells.drain(..) {
+                        row.push_element(cell.padded(1));
+                    }
+                    row.push()
+                        .expect("Every table row has a cell for each column");
+                }
+                Event::End(_) => break,
+                _ => {}
+            }
+        }
+        table
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_large_heading_font_size() {
+        assert!(render("# Fyrirsögn\n\n## Undirfyrirsögn", 250, 100.0).is_ok());
+    }
+}
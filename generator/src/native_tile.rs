@@ -0,0 +1,609 @@
+// Merging and tiling with lopdf instead of Pdfium, so making a book needs no native library.
+// Each page of the book becomes a form XObject that's drawn on the sheets with a transformation
+// matrix, like Pdfium's copy_into_x_object_form_object.
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+
+use lopdf::content::{Content, Operation};
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream, StringFormat};
+
+use crate::imposition::SheetSide;
+use crate::sheet::{self, Line, Rect, SheetCanvas, SheetGeometry};
+use crate::tile::{self, MergedPages, PdfPart};
+
+const POINTS_PER_MM: f64 = 72.0 / 25.4;
+
+fn mm(value: f64) -> f32 {
+    (value * POINTS_PER_MM) as f32
+}
+
+#[derive(Debug, Copy, Clone)]
+struct SourcePage {
+    doc: usize,
+    id: ObjectId,
+}
+
+// The pages of the book in order, still in the documents they were loaded from. None is a
+// blank page.
+pub struct NativeBook {
+    docs: Vec<Document>,
+    pages: Vec<Option<SourcePage>>,
+    // The size of the book's pages in points, which pages of other sizes are scaled to fit
+    page_size: (f32, f32),
+}
+
+impl NativeBook {
+    pub fn new(page_size_mm: (f64, f64)) -> NativeBook {
+        NativeBook {
+            docs: Vec::new(),
+            pages: Vec::new(),
+            page_size: (mm(page_size_mm.0), mm(page_size_mm.1)),
+        }
+    }
+
+    pub fn page_count(&self) -> usize {
+        self.pages.len()
+    }
+
+    fn push_document(
+        &mut self,
+        doc: Document,
+        page_indices: Option<Vec<u16>>,
+    ) -> Result<(), lopdf::Error> {
+        let index = self.docs.len();
+        let pages = doc.get_pages();
+        let numbers = match page_indices {
+            Some(indices) => indices.into_iter().map(|i| i as u32 + 1).collect(),
+            None => pages.keys().copied().collect::<Vec<_>>(),
+        };
+        let new_pages = numbers
+            .into_iter()
+            .map(|number| match pages.get(&number) {
+                Some(id) => Ok(Some(SourcePage {
+                    doc: index,
+                    id: *id,
+                })),
+                None => Err(lopdf::Error::PageNumberNotFound(number)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        self.pages.extend(new_pages);
+        self.docs.push(doc);
+        Ok(())
+    }
+
+    pub fn add_blank_pages(&mut self, count: usize) {
+        self.pages.extend(std::iter::repeat_n(None, count));
+    }
+
+    pub fn append(&mut self, other: NativeBook) {
+        let offset = self.docs.len();
+        self.docs.extend(other.docs);
+        self.pages.extend(other.pages.into_iter().map(|page| {
+            page.map(|page| SourcePage {
+                doc: page.doc + offset,
+                ..page
+            })
+        }));
+    }
+}
+
+impl MergedPages for NativeBook {
+    type Error = lopdf::Error;
+
+    fn page_count(&self) -> usize {
+        self.pages.len()
+    }
+
+    fn push_blank_pages(&mut self, count: usize) -> Result<(), lopdf::Error> {
+        self.add_blank_pages(count);
+        Ok(())
+    }
+
+    fn push_pdf(&mut self, pdf: Vec<u8>) -> Result<(), lopdf::Error> {
+        self.push_document(Document::load_mem(&pdf)?, None)
+    }
+
+    fn push_file(&mut self, path: &str, pages: Option<Vec<u16>>) -> Result<(), lopdf::Error> {
+        self.push_document(Document::load(path)?, pages)
+    }
+}
+
+// Also returns the page each part starts on in the merged book
+pub fn merge_pdfs(
+    pdfs: Vec<PdfPart>,
+    page_size_mm: (f64, f64),
+) -> Result<(NativeBook, Vec<usize>), lopdf::Error> {
+    let mut book = NativeBook::new(page_size_mm);
+    let starts = tile::merge_parts(&mut book, pdfs)?;
+    Ok((book, starts))
+}
+
+// Copies an object and everything it refers to from one document to another. The ids of the
+// objects that have already been copied are kept, so shared fonts and images are only copied once.
+fn import_object(
+    target: &mut Document,
+    source: &Document,
+    object: &Object,
+    ids: &mut HashMap<ObjectId, ObjectId>,
+) -> Object {
+    match object {
+        Object::Reference(id) => {
+            if let Some(new_id) = ids.get(id) {
+                return Object::Reference(*new_id);
+            }
+            let new_id = target.new_object_id();
+            ids.insert(*id, new_id);
+            let copy = match source.get_object(*id) {
+                Ok(object) => import_object(target, source, object, ids),
+                Err(_) => Object::Null,
+            };
+            target.set_object(new_id, copy);
+            Object::Reference(new_id)
+        }
+        Object::Array(array) => Object::Array(
+            array
+                .iter()
+                .map(|object| import_object(target, source, object, ids))
+                .collect(),
+        ),
+        Object::Dictionary(dict) => {
+            Object::Dictionary(import_dictionary(target, source, dict, ids))
+        }
+        Object::Stream(stream) => {
+            let dict = import_dictionary(target, source, &stream.dict, ids);
+            Object::Stream(Stream::new(dict, stream.content.clone()))
+        }
+        object => object.clone(),
+    }
+}
+
+fn import_dictionary(
+    target: &mut Document,
+    source: &Document,
+    dict: &Dictionary,
+    ids: &mut HashMap<ObjectId, ObjectId>,
+) -> Dictionary {
+    let mut copy = Dictionary::new();
+    for (key, value) in dict.iter() {
+        copy.set(key.clone(), import_object(target, source, value, ids));
+    }
+    copy
+}
+
+// Page attributes can be inherited from the page tree
+fn inherited<'a>(doc: &'a Document, page_id: ObjectId, key: &[u8]) -> Option<&'a Object> {
+    let mut node = doc.get_dictionary(page_id).ok()?;
+    loop {
+        if let Ok(value) = node.get(key) {
+            return Some(value);
+        }
+        let parent = node.get(b"Parent").and_then(Object::as_reference).ok()?;
+        node = doc.get_dictionary(parent).ok()?;
+    }
+}
+
+fn media_box(doc: &Document, page_id: ObjectId) -> Option<[f32; 4]> {
+    let object = inherited(doc, page_id, b"MediaBox")?;
+    let (_, object) = doc.dereference(object).ok()?;
+    match object.as_array().ok()?.as_slice() {
+        [x0, y0, x1, y1] => Some([
+            x0.as_float().ok()?,
+            y0.as_float().ok()?,
+            x1.as_float().ok()?,
+            y1.as_float().ok()?,
+        ]),
+        _ => None,
+    }
+}
+
+// A page as a form XObject in the tiled document, with the box it was drawn in
+struct PageForm {
+    id: ObjectId,
+    media_box: [f32; 4],
+}
+
+fn page_form(
+    target: &mut Document,
+    source: &Document,
+    page_id: ObjectId,
+    ids: &mut HashMap<ObjectId, ObjectId>,
+    page_size: (f32, f32),
+) -> Result<PageForm, lopdf::Error> {
+    let media_box = media_box(source, page_id).unwrap_or([0.0, 0.0, page_size.0, page_size.1]);
+    let resources = match inherited(source, page_id, b"Resources") {
+        Some(resources) => import_object(target, source, resources, ids),
+        None => Object::Dictionary(Dictionary::new()),
+    };
+    let content = source.get_page_content(page_id)?;
+
+    let mut dict = Dictionary::new();
+    dict.set("Type", "XObject");
+    dict.set("Subtype", "Form");
+    dict.set(
+        "BBox",
+        media_box
+            .iter()
+            .map(|v| (*v).into())
+            .collect::<Vec<Object>>(),
+    );
+    dict.set("Resources", resources);
+    let id = target.add_object(Stream::new(dict, content));
+    Ok(PageForm { id, media_box })
+}
+
+// Where to draw a page so it fills the tile, keeping its aspect ratio and centering it if it's
+// not the size of the book's pages
+fn page_matrix(form: &PageForm, page_size: (f32, f32), x: f32, y: f32, scale: f32) -> [f32; 6] {
+    let [x0, y0, x1, y1] = form.media_box;
+    let (width, height) = (x1 - x0, y1 - y0);
+    let fit = if (width - page_size.0).abs() < 1.0 && (height - page_size.1).abs() < 1.0 {
+        1.0
+    } else {
+        (page_size.0 / width).min(page_size.1 / height)
+    };
+    let offset_x = (page_size.0 - width * fit) / 2.0 * scale;
+    let offset_y = (page_size.1 - height * fit) / 2.0 * scale;
+    let scale = scale * fit;
+    [
+        scale,
+        0.0,
+        0.0,
+        scale,
+        x + offset_x - x0 * scale,
+        y + offset_y - y0 * scale,
+    ]
+}
+
+fn draw_lines(operations: &mut Vec<Operation>, lines: &[Line], thickness: f32) {
+    if lines.is_empty() {
+        return;
+    }
+    operations.push(Operation::new("q", vec![]));
+    operations.push(Operation::new("w", vec![thickness.into()]));
+    for line in lines {
+        operations.push(Operation::new(
+            "m",
+            vec![mm(line.from.0).into(), mm(line.from.1).into()],
+        ));
+        operations.push(Operation::new(
+            "l",
+            vec![mm(line.to.0).into(), mm(line.to.1).into()],
+        ));
+        operations.push(Operation::new("S", vec![]));
+    }
+    operations.push(Operation::new("Q", vec![]));
+}
+
+// The standard fonts are in WinAnsiEncoding, which has the Icelandic letters where Latin-1 has them
+fn win_ansi(text: &str) -> Vec<u8> {
+    text.chars()
+        .map(|c| match c {
+            '–' => 0x96,
+            '—' => 0x97,
+            '€' => 0x80,
+            c if (c as u32) < 0x80 || (0xa0..0x100).contains(&(c as u32)) => c as u8,
+            _ => b'?',
+        })
+        .collect()
+}
+
+struct NativeCanvas<'a> {
+    book: &'a NativeBook,
+    doc: Document,
+    pages_id: ObjectId,
+    font_id: ObjectId,
+    sheet_size: (f64, f64),
+    // The ids of the objects copied from each of the book's documents
+    ids: Vec<HashMap<ObjectId, ObjectId>>,
+    forms: HashMap<usize, PageForm>,
+    kids: Vec<Object>,
+    // The side being drawn
+    operations: Vec<Operation>,
+    x_objects: Dictionary,
+}
+
+impl SheetCanvas for NativeCanvas<'_> {
+    type Error = lopdf::Error;
+
+    fn start_side(&mut self) -> Result<(), lopdf::Error> {
+        self.operations.clear();
+        self.x_objects = Dictionary::new();
+        Ok(())
+    }
+
+    fn draw_page(
+        &mut self,
+        index: usize,
+        position: (f64, f64),
+        scale: f64,
+    ) -> Result<(), lopdf::Error> {
+        // Blank pages are left empty
+        let Some(Some(page)) = self.book.pages.get(index) else {
+            return Ok(());
+        };
+        let form = match self.forms.entry(index) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let source = &self.book.docs[page.doc];
+                let ids = &mut self.ids[page.doc];
+                let form = page_form(&mut self.doc, source, page.id, ids, self.book.page_size)?;
+                entry.insert(form)
+            }
+        };
+        let name = format!("P{}", index);
+        self.x_objects.set(name.clone(), form.id);
+
+        let matrix = page_matrix(
+            form,
+            self.book.page_size,
+            mm(position.0),
+            mm(position.1),
+            scale as f32,
+        );
+        self.operations.extend([
+            Operation::new("q", vec![]),
+            Operation::new("cm", matrix.iter().map(|v| (*v).into()).collect()),
+            Operation::new("Do", vec![Object::Name(name.into_bytes())]),
+            Operation::new("Q", vec![]),
+        ]);
+        Ok(())
+    }
+
+    fn draw_lines(&mut self, lines: &[Line], thickness: f32) -> Result<(), lopdf::Error> {
+        draw_lines(&mut self.operations, lines, thickness);
+        Ok(())
+    }
+
+    fn fill_rect(&mut self, rect: Rect) -> Result<(), lopdf::Error> {
+        self.operations.extend([
+            Operation::new(
+                "re",
+                vec![
+                    mm(rect.x).into(),
+                    mm(rect.y).into(),
+                    mm(rect.width).into(),
+                    mm(rect.height).into(),
+                ],
+            ),
+            Operation::new("f", vec![]),
+        ]);
+        Ok(())
+    }
+
+    fn draw_text(&mut self, position: (f64, f64), text: &str) -> Result<(), lopdf::Error> {
+        self.operations.extend([
+            Operation::new("BT", vec![]),
+            Operation::new("Tf", vec!["F1".into(), 7.into()]),
+            Operation::new("Td", vec![mm(position.0).into(), mm(position.1).into()]),
+            Operation::new(
+                "Tj",
+                vec![Object::String(win_ansi(text), StringFormat::Literal)],
+            ),
+            Operation::new("ET", vec![]),
+        ]);
+        Ok(())
+    }
+
+    fn finish_side(&mut self, rotated: bool) -> Result<(), lopdf::Error> {
+        let operations = std::mem::take(&mut self.operations);
+        let content = Content { operations }.encode()?;
+        let content_id = self.doc.add_object(Stream::new(Dictionary::new(), content));
+        let x_objects = std::mem::take(&mut self.x_objects);
+        let mut page = Dictionary::from_iter(vec![
+            ("Type", Object::from("Page")),
+            ("Parent", self.pages_id.into()),
+            (
+                "MediaBox",
+                vec![
+                    0.into(),
+                    0.into(),
+                    mm(self.sheet_size.0).into(),
+                    mm(self.sheet_size.1).into(),
+                ]
+                .into(),
+            ),
+            ("Contents", content_id.into()),
+            (
+                "Resources",
+                Dictionary::from_iter(vec![
+                    ("XObject", Object::from(x_objects)),
+                    (
+                        "Font",
+                        Dictionary::from_iter(vec![("F1", Object::from(self.font_id))]).into(),
+                    ),
+                ])
+                .into(),
+            ),
+        ]);
+        if rotated {
+            page.set("Rotate", 180);
+        }
+        self.kids.push(Object::from(self.doc.add_object(page)));
+        Ok(())
+    }
+}
+
+// The sides of the sheets as a new document
+pub fn tile_pages(
+    book: &NativeBook,
+    sides: &[SheetSide],
+    sheet_size: (f64, f64),
+    copies: &[SheetGeometry],
+    add_separator: bool,
+    title: &str,
+) -> Result<Vec<u8>, lopdf::Error> {
+    let mut doc = Document::with_version("1.5");
+    let pages_id = doc.new_object_id();
+    let font_id = doc.add_object(Dictionary::from_iter(vec![
+        ("Type", Object::from("Font")),
+        ("Subtype", "Type1".into()),
+        ("BaseFont", "Helvetica".into()),
+        ("Encoding", "WinAnsiEncoding".into()),
+    ]));
+    let mut canvas = NativeCanvas {
+        book,
+        doc,
+        pages_id,
+        font_id,
+        sheet_size,
+        ids: vec![HashMap::new(); book.docs.len()],
+        forms: HashMap::new(),
+        kids: Vec::with_capacity(sides.len()),
+        operations: Vec::new(),
+        x_objects: Dictionary::new(),
+    };
+    sheet::draw_sides(&mut canvas, sides, copies, add_separator, title)?;
+    let NativeCanvas { mut doc, kids, .. } = canvas;
+
+    let count = kids.len() as i64;
+    doc.objects.insert(
+        pages_id,
+        Object::Dictionary(Dictionary::from_iter(vec![
+            ("Type", Object::from("Pages")),
+            ("Kids", kids.into()),
+            ("Count", count.into()),
+        ])),
+    );
+    let catalog_id = doc.add_object(Dictionary::from_iter(vec![
+        ("Type", Object::from("Catalog")),
+        ("Pages", pages_id.into()),
+    ]));
+    doc.trailer.set("Root", catalog_id);
+    doc.compress();
+
+    let mut bytes = Vec::new();
+    doc.save_to(&mut bytes)?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::imposition::{impose, DuplexFlip, Grid, Imposition};
+    use crate::sheet::{MarkSettings, Rect};
+
+    // A document with the given number of pages, each saying its number
+    fn numbered_pages(count: usize, size: (f32, f32)) -> Vec<u8> {
+        let mut doc = Document::with_version("1.5");
+        let pages_id = doc.new_object_id();
+        let kids = (0..count)
+            .map(|i| {
+                let content = format!("BT ({}) Tj ET", i + 1).into_bytes();
+                let content_id = doc.add_object(Stream::new(Dictionary::new(), content));
+                Object::from(doc.add_object(Dictionary::from_iter(vec![
+                    ("Type", Object::from("Page")),
+                    ("Parent", pages_id.into()),
+                    ("Contents", content_id.into()),
+                ])))
+            })
+            .collect::<Vec<_>>();
+        doc.objects.insert(
+            pages_id,
+            Object::Dictionary(Dictionary::from_iter(vec![
+                ("Type", Object::from("Pages")),
+                ("Kids", kids.into()),
+                ("Count", (count as i64).into()),
+                (
+                    "MediaBox",
+                    vec![0.into(), 0.into(), size.0.into(), size.1.into()].into(),
+                ),
+            ])),
+        );
+        let catalog_id = doc.add_object(Dictionary::from_iter(vec![
+            ("Type", Object::from("Catalog")),
+            ("Pages", pages_id.into()),
+        ]));
+        doc.trailer.set("Root", catalog_id);
+        let mut bytes = Vec::new();
+        doc.save_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_booklet_sheets() {
+        let a5 = (148.0, 210.0);
+        let (mut book, starts) = merge_pdfs(
+            vec![
+                PdfPart::Generated(numbered_pages(3, (mm(a5.0), mm(a5.1)))),
+                // Twice the size, so it's shrunk to fit
+                PdfPart::Generated(numbered_pages(2, (mm(2.0 * a5.0), mm(2.0 * a5.1)))),
+            ],
+            a5,
+        )
+        .unwrap();
+        assert_eq!(starts, [0, 3]);
+        book.add_blank_pages(3);
+        assert_eq!(book.page_count(), 8);
+
+        let grid = Grid {
+            rows: 1,
+            columns: 2,
+            portrait: false,
+        };
+        let sides = impose(Imposition::Booklet, 8, grid, DuplexFlip::LongEdge, 1).unwrap();
+        let marks = MarkSettings {
+            crop_marks: true,
+            bleed_mm: 0.0,
+            folded: true,
+            creep_mm: 0.0,
+        };
+        let area = Rect {
+            x: 0.0,
+            y: 0.0,
+            width: 297.0,
+            height: 210.0,
+        };
+        let geometry = SheetGeometry::new(area, grid, a5, marks, 0.0);
+        let bytes =
+            tile_pages(&book, &sides, (297.0, 210.0), &[geometry], true, "Söngbók").unwrap();
+
+        let doc = Document::load_mem(&bytes).unwrap();
+        let pages = doc.get_pages();
+        assert_eq!(pages.len(), 4);
+        let rotations = pages
+            .values()
+            .map(|id| doc.get_dictionary(*id).unwrap().get(b"Rotate").is_ok())
+            .collect::<Vec<_>>();
+        assert_eq!(rotations, [false, true, false, true]);
+
+        // The first side has the last page, which is blank, and the first page
+        let first = pages[&1];
+        let (resources, _) = doc.get_page_resources(first);
+        let x_objects = resources
+            .unwrap()
+            .get(b"XObject")
+            .unwrap()
+            .as_dict()
+            .unwrap();
+        assert_eq!(x_objects.len(), 1);
+        assert!(x_objects.get(b"P0").is_ok());
+        let content = String::from_utf8_lossy(&doc.get_page_content(first).unwrap()).into_owned();
+        assert!(content.contains("/P0 Do"));
+    }
+
+    #[test]
+    fn test_win_ansi() {
+        assert_eq!(win_ansi("örk – ð€ő"), b"\xf6rk \x96 \xf0\x80?");
+    }
+
+    #[test]
+    fn test_page_matrix_fits_larger_pages() {
+        let form = PageForm {
+            id: (1, 0),
+            media_box: [0.0, 0.0, 200.0, 100.0],
+        };
+        let matrix = page_matrix(&form, (100.0, 100.0), 10.0, 20.0, 1.0);
+        assert_eq!(matrix, [0.5, 0.0, 0.0, 0.5, 10.0, 45.0]);
+    }
+
+    #[test]
+    fn test_missing_page_is_an_error() {
+        let mut book = NativeBook::new((148.0, 210.0));
+        let pdf = Document::load_mem(&numbered_pages(2, (mm(148.0), mm(210.0)))).unwrap();
+        assert!(matches!(
+            book.push_document(pdf, Some(vec![0, 2])),
+            Err(lopdf::Error::PageNumberNotFound(3))
+        ));
+        assert_eq!(book.page_count(), 0);
+    }
+}
@@ -0,0 +1,54 @@
+use pdfium_render::prelude::*;
+
+use crate::GenerationError;
+
+// One side of an imposed sheet as an image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SheetPreview {
+    // Counted from one, like the sheet numbers in the slug
+    pub sheet: usize,
+    pub back: bool,
+    pub png: Vec<u8>,
+}
+
+impl SheetPreview {
+    pub fn file_name(&self) -> String {
+        let side = if self.back { "back" } else { "front" };
+        format!("sheet-{:02}-{}.png", self.sheet, side)
+    }
+}
+
+// PDF sizes are in points, which are 1/72 of an inch
+pub fn render_png(page: &PdfPage, dpi: f32) -> Result<Vec<u8>, GenerationError> {
+    let bitmap = page.render_with_config(
+        &PdfRenderConfig::new()
+            .scale_page_by_factor(dpi / 72.0)
+            .render_form_data(false),
+    )?;
+    let (width, height) = (bitmap.width() as u32, bitmap.height() as u32);
+    let mut png = Vec::new();
+    image::png::PngEncoder::new(&mut png)
+        .encode(
+            &bitmap.as_rgba_bytes(),
+            width,
+            height,
+            image::ColorType::Rgba8,
+        )
+        .map_err(GenerationError::ImageError)?;
+    Ok(png)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_name() {
+        let preview = SheetPreview {
+            sheet: 3,
+            back: true,
+            png: Vec::new(),
+        };
+        assert_eq!(preview.file_name(), "sheet-03-back.png");
+    }
+}
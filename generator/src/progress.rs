@@ -0,0 +1,176 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::GenerationError;
+
+// The steps of making a book, in the order they happen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Progress {
+    FontLoaded,
+    // Counted from one, so the last song is done when done == total
+    SongRendered { done: usize, total: usize },
+    Merging,
+    Imposing,
+    Saving,
+}
+
+impl Progress {
+    // Roughly how much of the work is done, for a progress bar. Laying out the songs takes most
+    // of the time.
+    pub fn fraction(&self) -> f32 {
+        match self {
+            Progress::FontLoaded => 0.05,
+            Progress::SongRendered { done, total } => {
+                0.05 + 0.8 * *done as f32 / (*total).max(1) as f32
+            }
+            Progress::Merging => 0.85,
+            Progress::Imposing => 0.9,
+            Progress::Saving => 0.95,
+        }
+    }
+}
+
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Progress::FontLoaded => write!(f, "Letur hlaðið"),
+            Progress::SongRendered { done, total } => write!(f, "Lag {}/{} sett upp", done, total),
+            Progress::Merging => write!(f, "Sameinar síður"),
+            Progress::Imposing => write!(f, "Raðar síðum á arkir"),
+            Progress::Saving => write!(f, "Vistar PDF"),
+        }
+    }
+}
+
+// Shared between the one making the book and the one who wants it stopped
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+fn ignore(_: Progress) {}
+
+fn print_warning(warning: &str) {
+    println!("{}", warning);
+}
+
+// Passed through the generation, which stops with GenerationError::Cancelled at the next step
+// after the token is cancelled. Parts of the book may be made on several threads at once, so
+// the progress may be reported from any of them.
+pub struct ProgressReporter<'a> {
+    on_progress: &'a (dyn Fn(Progress) + Sync),
+    on_warning: &'a (dyn Fn(&str) + Sync),
+    cancel: CancelToken,
+}
+
+impl<'a> ProgressReporter<'a> {
+    pub fn new(
+        on_progress: &'a (dyn Fn(Progress) + Sync),
+        cancel: CancelToken,
+    ) -> ProgressReporter<'a> {
+        ProgressReporter {
+            on_progress,
+            on_warning: &print_warning,
+            cancel,
+        }
+    }
+
+    // For when nobody is watching and the generation can't be stopped. Warnings are still printed.
+    pub fn silent() -> ProgressReporter<'static> {
+        ProgressReporter::new(&ignore, CancelToken::new())
+    }
+
+    // Warnings are printed unless someone else wants them
+    pub fn with_warnings(self, on_warning: &'a (dyn Fn(&str) + Sync)) -> ProgressReporter<'a> {
+        ProgressReporter { on_warning, ..self }
+    }
+
+    // Something the user should know about the book, which doesn't stop it from being made
+    pub fn warn(&self, warning: &str) {
+        (self.on_warning)(warning);
+    }
+
+    pub fn report(&self, progress: Progress) -> Result<(), GenerationError> {
+        if self.cancel.is_cancelled() {
+            return Err(GenerationError::Cancelled);
+        }
+        (self.on_progress)(progress);
+        Ok(())
+    }
+}
+
+// Counts the songs as they're laid out, whichever section they're in
+pub struct SongCounter<'a> {
+    reporter: &'a ProgressReporter<'a>,
+    done: AtomicUsize,
+    total: usize,
+}
+
+impl<'a> SongCounter<'a> {
+    pub fn new(reporter: &'a ProgressReporter<'a>, total: usize) -> SongCounter<'a> {
+        SongCounter {
+            reporter,
+            done: AtomicUsize::new(0),
+            total,
+        }
+    }
+
+    pub fn song_done(&self) -> Result<(), GenerationError> {
+        let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+        self.reporter.report(Progress::SongRendered {
+            done,
+            total: self.total,
+        })
+    }
+
+    pub fn warn(&self, warning: &str) {
+        self.reporter.warn(warning);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_report_until_cancelled() {
+        let seen = Mutex::new(Vec::new());
+        let on_progress = |progress| seen.lock().unwrap().push(progress);
+        let cancel = CancelToken::new();
+        let reporter = ProgressReporter::new(&on_progress, cancel.clone());
+
+        assert!(reporter.report(Progress::FontLoaded).is_ok());
+        cancel.cancel();
+        assert!(matches!(
+            reporter.report(Progress::Merging),
+            Err(GenerationError::Cancelled)
+        ));
+        assert_eq!(*seen.lock().unwrap(), [Progress::FontLoaded]);
+    }
+
+    #[test]
+    fn test_warnings_after_cancel() {
+        let warnings = Mutex::new(Vec::new());
+        let on_warning = |warning: &str| warnings.lock().unwrap().push(warning.to_owned());
+        let cancel = CancelToken::new();
+        let reporter = ProgressReporter::new(&ignore, cancel.clone()).with_warnings(&on_warning);
+
+        cancel.cancel();
+        reporter.warn("Lagið passar ekki");
+        assert_eq!(*warnings.lock().unwrap(), ["Lagið passar ekki"]);
+    }
+}
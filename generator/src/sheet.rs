@@ -0,0 +1,592 @@
+use serde::{Deserialize, Serialize};
+
+use crate::imposition::{Grid, SheetSide, SpineMark};
+
+// Lengths are in millimeters, with the origin in the bottom left corner of the sheet like in PDF
+const MARK_OFFSET_MM: f64 = 2.0;
+const MARK_LENGTH_MM: f64 = 5.0;
+const SLUG_HEIGHT_MM: f64 = 5.0;
+const DASH_LENGTH_MM: f64 = 1.0;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GridSize {
+    pub rows: u8,
+    pub columns: u8,
+}
+
+// The grid of pages that fits in an area of the sheet, and how much the pages have to be shrunk
+// to fit it. Without a grid size as many pages are put in the area as fit at their real size.
+fn grid_in(
+    area: (f64, f64),
+    page: (f64, f64),
+    grid_size: Option<GridSize>,
+    paired_columns: bool,
+) -> (Grid, f64) {
+    let (rows, columns) = match grid_size {
+        Some(size) => (size.rows.max(1) as usize, size.columns.max(1) as usize),
+        None => {
+            // Leave a little room for rounding, so two A5 pages fit on an A4 sheet
+            let fits = |area_length: f64, page_length: f64| {
+                ((area_length + 0.01) / page_length).floor().max(1.0) as usize
+            };
+            let columns = fits(area.0, page.0);
+            let columns = if paired_columns && columns > 1 {
+                columns - columns % 2
+            } else {
+                columns
+            };
+            (fits(area.1, page.1), columns)
+        }
+    };
+    let grid = Grid {
+        rows,
+        columns,
+        portrait: area.1 >= area.0,
+    };
+    let scale = (area.0 / columns as f64 / page.0).min(area.1 / rows as f64 / page.1);
+    (grid, scale)
+}
+
+// The sheet turned whichever way fits the pages best, split into an area for each copy of the
+// book
+#[derive(Debug, Clone, PartialEq)]
+pub struct SheetLayout {
+    pub size: (f64, f64),
+    pub grid: Grid,
+    pub copies: Vec<Rect>,
+}
+
+// Folded pages have to be in pairs, then more pages on each side is better, and then having to
+// shrink them less
+pub fn layout_sheet(
+    paper: (f64, f64),
+    page: (f64, f64),
+    grid_size: Option<GridSize>,
+    paired_columns: bool,
+    copies: usize,
+) -> SheetLayout {
+    let copies = copies.max(1);
+    let (short, long) = (paper.0.min(paper.1), paper.0.max(paper.1));
+
+    let mut best: Option<((bool, usize, f64), SheetLayout)> = None;
+    for size in [(short, long), (long, short)] {
+        for copy_rows in (1..=copies).filter(|rows| copies.is_multiple_of(*rows)) {
+            let copy_columns = copies / copy_rows;
+            let area = (size.0 / copy_columns as f64, size.1 / copy_rows as f64);
+            let (mut grid, scale) = grid_in(area, page, grid_size, paired_columns);
+            // The way the sheet is turned over depends on the whole sheet, not the copy
+            grid.portrait = size.1 >= size.0;
+
+            let score = (
+                !paired_columns || grid.columns % 2 == 0,
+                grid.tiles(),
+                scale,
+            );
+            if best.as_ref().is_some_and(|(best, _)| score <= *best) {
+                continue;
+            }
+            let copies = (0..copy_rows)
+                .flat_map(|row| {
+                    (0..copy_columns).map(move |column| Rect {
+                        x: column as f64 * area.0,
+                        y: size.1 - (row + 1) as f64 * area.1,
+                        width: area.0,
+                        height: area.1,
+                    })
+                })
+                .collect();
+            best = Some((score, SheetLayout { size, grid, copies }));
+        }
+    }
+    best.map(|(_, layout)| layout)
+        .expect("There's always at least one way to lay out the sheet")
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+pub struct Rect {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl Rect {
+    pub fn right(&self) -> f64 {
+        self.x + self.width
+    }
+
+    pub fn top(&self) -> f64 {
+        self.y + self.height
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+pub struct Line {
+    pub from: (f64, f64),
+    pub to: (f64, f64),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MarkSettings {
+    pub crop_marks: bool,
+    pub bleed_mm: f64,
+    // Booklet pages are folded apart in pairs, so there's no bleed between the two pages
+    pub folded: bool,
+    // How far a page is moved toward the spine for each leaf folded around it
+    pub creep_mm: f64,
+}
+
+// Where the pages go on a sheet. The tiles are the trimmed pages, and the bleed around them is
+// left empty so the cuts don't have to be exact.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SheetGeometry {
+    // The part of the sheet for this copy of the book
+    pub area: Rect,
+    pub scale: f64,
+    pub tiles: Vec<Rect>,
+    // The tiles and their bleed, which the marks are drawn outside of
+    pub bleed_box: Rect,
+    pub marks: MarkSettings,
+    pub grid: Grid,
+}
+
+impl SheetGeometry {
+    // The pages are placed at their real size if they fit, and shrunk to fit otherwise. Padding
+    // shrinks them further, for printers that can't print all the way to the edge of the sheet.
+    pub fn new(
+        area: Rect,
+        grid: Grid,
+        page: (f64, f64),
+        marks: MarkSettings,
+        padding: f64,
+    ) -> SheetGeometry {
+        let (page_width, page_height) = page;
+        let margin = if marks.crop_marks {
+            MARK_OFFSET_MM + MARK_LENGTH_MM + SLUG_HEIGHT_MM
+        } else {
+            0.0
+        };
+
+        // Bleed before and after each group of columns that's cut out together
+        let group = if marks.folded { 2 } else { 1 };
+        let mut column_offsets = Vec::with_capacity(grid.columns);
+        let mut block_width = 0.0;
+        for column in 0..grid.columns {
+            if column % group == 0 {
+                block_width += marks.bleed_mm;
+            }
+            column_offsets.push(block_width);
+            block_width += page_width;
+            if column % group == group - 1 || column == grid.columns - 1 {
+                block_width += marks.bleed_mm;
+            }
+        }
+        let row_height = page_height + 2.0 * marks.bleed_mm;
+        let block_height = row_height * grid.rows as f64;
+
+        let available_width = area.width - 2.0 * margin;
+        let available_height = area.height - 2.0 * margin;
+        let scale = (available_width / block_width)
+            .min(available_height / block_height)
+            .min(1.0)
+            * (1.0 - padding);
+
+        let bleed_box = Rect {
+            x: area.x + (area.width - block_width * scale) / 2.0,
+            y: area.y + (area.height - block_height * scale) / 2.0,
+            width: block_width * scale,
+            height: block_height * scale,
+        };
+        let mut tiles = Vec::with_capacity(grid.tiles());
+        for row in 0..grid.rows {
+            for offset in &column_offsets {
+                tiles.push(Rect {
+                    x: bleed_box.x + offset * scale,
+                    y: bleed_box.top()
+                        - (row as f64 * row_height + marks.bleed_mm + page_height) * scale,
+                    width: page_width * scale,
+                    height: page_height * scale,
+                });
+            }
+        }
+
+        SheetGeometry {
+            area,
+            scale,
+            tiles,
+            bleed_box,
+            marks,
+            grid,
+        }
+    }
+
+    // The left and right edges of the tiles, and their bottom and top edges
+    fn trim_edges(&self) -> (Vec<f64>, Vec<f64>) {
+        let mut xs = Vec::new();
+        let mut ys = Vec::new();
+        for tile in &self.tiles {
+            xs.extend([tile.x, tile.right()]);
+            ys.extend([tile.y, tile.top()]);
+        }
+        for edges in [&mut xs, &mut ys] {
+            edges.sort_by(|a, b| a.total_cmp(b));
+            edges.dedup_by(|a, b| (*a - *b).abs() < 0.01);
+        }
+        (xs, ys)
+    }
+
+    // Lines along every edge of the tiles, across the whole area
+    pub fn separators(&self) -> Vec<Line> {
+        let (xs, ys) = self.trim_edges();
+        let area = self.area;
+        let vertical = xs.into_iter().map(|x| Line {
+            from: (x, area.y),
+            to: (x, area.top()),
+        });
+        let horizontal = ys.into_iter().map(|y| Line {
+            from: (area.x, y),
+            to: (area.right(), y),
+        });
+        vertical.chain(horizontal).collect()
+    }
+
+    // The cuts between the pages and around them, across the bleed
+    pub fn cut_lines(&self) -> Vec<Line> {
+        let (xs, ys) = self.trim_edges();
+        let folds = self.folds();
+        let bleed_box = self.bleed_box;
+        let vertical = xs
+            .into_iter()
+            .filter(|x| !folds.iter().any(|f| (f - x).abs() < 0.01))
+            .map(|x| Line {
+                from: (x, bleed_box.y),
+                to: (x, bleed_box.top()),
+            });
+        let horizontal = ys.into_iter().map(|y| Line {
+            from: (bleed_box.x, y),
+            to: (bleed_box.right(), y),
+        });
+        vertical.chain(horizontal).collect()
+    }
+
+    pub fn fold_lines(&self) -> Vec<Line> {
+        let bleed_box = self.bleed_box;
+        self.folds()
+            .into_iter()
+            .map(|x| Line {
+                from: (x, bleed_box.y),
+                to: (x, bleed_box.top()),
+            })
+            .collect()
+    }
+
+    // Short lines outside the bleed that line up with the cuts
+    pub fn crop_marks(&self) -> Vec<Line> {
+        if !self.marks.crop_marks {
+            return Vec::new();
+        }
+
+        let (xs, ys) = self.trim_edges();
+        let folds = self.folds();
+        let bleed_box = self.bleed_box;
+        let (near, far) = (MARK_OFFSET_MM, MARK_OFFSET_MM + MARK_LENGTH_MM);
+        let mut lines = Vec::new();
+        for x in xs
+            .into_iter()
+            .filter(|x| !folds.iter().any(|f| (f - x).abs() < 0.01))
+        {
+            lines.push(Line {
+                from: (x, bleed_box.top() + near),
+                to: (x, bleed_box.top() + far),
+            });
+            lines.push(Line {
+                from: (x, bleed_box.y - near),
+                to: (x, bleed_box.y - far),
+            });
+        }
+        for y in ys {
+            lines.push(Line {
+                from: (bleed_box.x - near, y),
+                to: (bleed_box.x - far, y),
+            });
+            lines.push(Line {
+                from: (bleed_box.right() + near, y),
+                to: (bleed_box.right() + far, y),
+            });
+        }
+        lines
+    }
+
+    fn folds(&self) -> Vec<f64> {
+        if !self.marks.folded {
+            return Vec::new();
+        }
+        self.tiles[..self.grid.columns]
+            .iter()
+            .step_by(2)
+            .map(|tile| tile.right())
+            .collect()
+    }
+
+    // Dashed lines outside the bleed that line up with the folds
+    pub fn fold_marks(&self) -> Vec<Line> {
+        if !self.marks.crop_marks {
+            return Vec::new();
+        }
+
+        let bleed_box = self.bleed_box;
+        let mut lines = Vec::new();
+        for x in self.folds() {
+            for (start, direction) in [(bleed_box.top(), 1.0), (bleed_box.y, -1.0)] {
+                let mut offset = MARK_OFFSET_MM;
+                while offset < MARK_OFFSET_MM + MARK_LENGTH_MM {
+                    lines.push(Line {
+                        from: (x, start + direction * offset),
+                        to: (x, start + direction * (offset + DASH_LENGTH_MM)),
+                    });
+                    offset += 2.0 * DASH_LENGTH_MM;
+                }
+            }
+        }
+        lines
+    }
+
+    // A filled bar across the fold, stepped down the spine by the signature's number
+    pub fn spine_mark(&self, mark: &SpineMark) -> Rect {
+        let tile = self.tiles[mark.tile];
+        let step = (tile.height / (mark.signature_count + 1) as f64).min(10.0);
+        let top = tile.top() - step * mark.signature as f64;
+        Rect {
+            x: tile.right() - 1.5,
+            y: top - step,
+            width: 3.0,
+            height: step,
+        }
+    }
+
+    // Where the sheet's description starts, below the crop marks
+    pub fn slug_position(&self) -> (f64, f64) {
+        (
+            self.bleed_box.x,
+            self.bleed_box.y - MARK_OFFSET_MM - MARK_LENGTH_MM - SLUG_HEIGHT_MM + 1.0,
+        )
+    }
+}
+
+// Says which sheet this is, below the crop marks
+pub fn slug(title: &str, sheet: usize, sheet_count: usize, back: bool) -> String {
+    let side_name = if back { "bakhlið" } else { "framhlið" };
+    let slug = format!("{} – örk {}/{}, {}", title, sheet, sheet_count, side_name);
+    slug.trim_start_matches(" – ").to_owned()
+}
+
+// What each backend has to be able to draw on the sheets. Positions and sizes are in millimeters.
+pub trait SheetCanvas {
+    type Error;
+
+    fn start_side(&mut self) -> Result<(), Self::Error>;
+    // The page of the book with the given index, with its bottom left corner at the position
+    fn draw_page(
+        &mut self,
+        index: usize,
+        position: (f64, f64),
+        scale: f64,
+    ) -> Result<(), Self::Error>;
+    fn draw_lines(&mut self, lines: &[Line], thickness: f32) -> Result<(), Self::Error>;
+    fn fill_rect(&mut self, rect: Rect) -> Result<(), Self::Error>;
+    fn draw_text(&mut self, position: (f64, f64), text: &str) -> Result<(), Self::Error>;
+    fn finish_side(&mut self, rotated: bool) -> Result<(), Self::Error>;
+}
+
+// Places each page on its tile without scaling it, unless it has to be shrunk to fit the sheet.
+// Each copy of the book gets the same pages, with its own marks, so the copies can be cut apart.
+pub fn draw_sides<C: SheetCanvas>(
+    canvas: &mut C,
+    sides: &[SheetSide],
+    copies: &[SheetGeometry],
+    add_separator: bool,
+    title: &str,
+) -> Result<(), C::Error> {
+    let sheet_count = sides.iter().filter(|side| !side.back).count();
+    let mut sheet = 0;
+
+    for side in sides {
+        if !side.back {
+            sheet += 1;
+        }
+        canvas.start_side()?;
+
+        for geometry in copies {
+            for ((slot, tile), creep) in side.slots.iter().zip(&geometry.tiles).zip(&side.creep) {
+                let Some(index) = *slot else {
+                    continue;
+                };
+                let shift = *creep as f64 * geometry.marks.creep_mm;
+                canvas.draw_page(index, (tile.x + shift, tile.y), geometry.scale)?;
+            }
+
+            if add_separator {
+                canvas.draw_lines(&geometry.separators(), 1.0)?;
+            }
+            canvas.draw_lines(&geometry.crop_marks(), 0.25)?;
+            canvas.draw_lines(&geometry.fold_marks(), 0.25)?;
+            for mark in &side.spine_marks {
+                canvas.fill_rect(geometry.spine_mark(mark))?;
+            }
+
+            if geometry.marks.crop_marks {
+                let slug = slug(title, sheet, sheet_count, side.back);
+                canvas.draw_text(geometry.slug_position(), &slug)?;
+            }
+        }
+
+        canvas.finish_side(side.rotated)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A4_LANDSCAPE: Rect = Rect {
+        x: 0.0,
+        y: 0.0,
+        width: 297.0,
+        height: 210.0,
+    };
+    const A5: (f64, f64) = (148.0, 210.0);
+    const A5_ON_A4: Grid = Grid {
+        rows: 1,
+        columns: 2,
+        portrait: false,
+    };
+    const A7_ON_A4: Grid = Grid {
+        rows: 2,
+        columns: 4,
+        portrait: false,
+    };
+
+    #[test]
+    fn test_layout_sheet() {
+        let a4 = (210.0, 297.0);
+        let grid = |page, size| layout_sheet(a4, page, size, false, 1).grid;
+        assert_eq!(grid((74.0, 105.0), None), A7_ON_A4);
+        assert_eq!(grid((148.0, 210.0), None), A5_ON_A4);
+        let layout = layout_sheet(a4, (105.0, 148.0), None, false, 1);
+        assert_eq!(
+            layout.grid,
+            Grid {
+                rows: 2,
+                columns: 2,
+                portrait: true
+            }
+        );
+        assert_eq!(layout.size, a4);
+
+        // Two A6 pages side by side fit better on a landscape A4 sheet than stacked on a
+        // portrait one
+        let two_up = Some(GridSize {
+            rows: 1,
+            columns: 2,
+        });
+        assert!(!grid((105.0, 148.0), two_up).portrait);
+
+        // Folded pages come in pairs
+        let letter = (215.9, 279.4);
+        let folded = layout_sheet(letter, (74.0, 105.0), None, true, 1).grid;
+        assert_eq!(folded.columns % 2, 0);
+    }
+
+    #[test]
+    fn test_step_and_repeat() {
+        // Two A6 booklets on an A4 sheet, one above the other
+        let layout = layout_sheet((210.0, 297.0), (105.0, 148.0), None, true, 2);
+        assert_eq!(
+            layout.grid,
+            Grid {
+                rows: 1,
+                columns: 2,
+                portrait: true
+            }
+        );
+        assert_eq!(
+            layout.copies,
+            [
+                Rect {
+                    x: 0.0,
+                    y: 148.5,
+                    width: 210.0,
+                    height: 148.5
+                },
+                Rect {
+                    x: 0.0,
+                    y: 0.0,
+                    width: 210.0,
+                    height: 148.5
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pages_at_real_size() {
+        let marks = MarkSettings {
+            crop_marks: false,
+            bleed_mm: 0.0,
+            folded: false,
+            creep_mm: 0.0,
+        };
+        let geometry = SheetGeometry::new(A4_LANDSCAPE, A5_ON_A4, A5, marks, 0.0);
+        assert_eq!(geometry.scale, 1.0);
+        assert_eq!(
+            geometry.tiles,
+            [
+                Rect {
+                    x: 0.5,
+                    y: 0.0,
+                    width: 148.0,
+                    height: 210.0
+                },
+                Rect {
+                    x: 148.5,
+                    y: 0.0,
+                    width: 148.0,
+                    height: 210.0
+                },
+            ]
+        );
+        assert!(geometry.crop_marks().is_empty());
+        assert_eq!(geometry.separators().len(), 5);
+    }
+
+    #[test]
+    fn test_marks_outside_bleed() {
+        let marks = MarkSettings {
+            crop_marks: true,
+            bleed_mm: 3.0,
+            folded: true,
+            creep_mm: 0.0,
+        };
+        let geometry = SheetGeometry::new(A4_LANDSCAPE, A5_ON_A4, A5, marks, 0.0);
+        assert!(geometry.scale < 1.0);
+        // The folded pair has no bleed between the pages
+        assert!((geometry.tiles[0].right() - geometry.tiles[1].x).abs() < 1e-9);
+
+        // Two cuts at the sides of the pair, none at the fold, and two at the top and bottom
+        assert_eq!(geometry.crop_marks().len(), 2 * 2 + 2 * 2);
+        let bleed_box = geometry.bleed_box;
+        for line in geometry.crop_marks().iter().chain(&geometry.fold_marks()) {
+            for (x, y) in [line.from, line.to] {
+                let inside = x > bleed_box.x
+                    && x < bleed_box.right()
+                    && y > bleed_box.y
+                    && y < bleed_box.top();
+                assert!(!inside);
+                assert!((0.0..=A4_LANDSCAPE.width).contains(&x));
+                assert!((0.0..=A4_LANDSCAPE.height).contains(&y));
+            }
+        }
+        assert!(!geometry.fold_marks().is_empty());
+    }
+}
@@ -3,6 +3,9 @@ use std::fmt;
 use pdfium_render::prelude::*;
 use serde::{Deserialize, Serialize};
 
+use crate::imposition::SheetSide;
+use crate::sheet::{self, Line, Rect, SheetCanvas, SheetGeometry};
+
 trait SumUntilIndex<T> {
     fn sum_until_index(self) -> Vec<T>;
 }
@@ -21,133 +24,380 @@ where
     }
 }
 
-pub fn merge_pdfs<'a>(
-    pdfium: &'a Pdfium,
-    mut pdfs: Vec<Vec<u8>>,
-) -> Result<PdfDocument<'a>, PdfiumError> {
-    if pdfs.is_empty() {
-        return Ok(pdfium.create_new_pdf()?);
-    } else if pdfs.len() == 1 {
-        return pdfium.load_pdf_from_byte_vec(pdfs.remove(0), None);
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PdfPart {
+    Generated(Vec<u8>),
+    // Starts on an even page, so that a song longer than a page can be read across a spread
+    Spread(Vec<u8>),
+    // Zero-based page indices, or None for the whole file
+    File {
+        path: String,
+        pages: Option<Vec<PdfPageIndex>>,
+    },
+}
+
+impl From<Vec<u8>> for PdfPart {
+    fn from(value: Vec<u8>) -> Self {
+        Self::Generated(value)
     }
+}
+
+// The book as it's being merged by one of the backends
+pub trait MergedPages {
+    type Error;
 
-    pdfs.into_iter()
-        .map(|pdf| pdfium.load_pdf_from_byte_vec(pdf, None))
-        .collect::<Result<Vec<_>, _>>()?
-        .into_iter()
-        .try_fold(pdfium.create_new_pdf()?, |mut acc, pdf| {
-            acc.pages_mut().append(&pdf).map(|()| acc)
-        })
+    fn page_count(&self) -> usize;
+    fn push_blank_pages(&mut self, count: usize) -> Result<(), Self::Error>;
+    fn push_pdf(&mut self, pdf: Vec<u8>) -> Result<(), Self::Error>;
+    fn push_file(
+        &mut self,
+        path: &str,
+        pages: Option<Vec<PdfPageIndex>>,
+    ) -> Result<(), Self::Error>;
 }
 
-pub fn mix_first_and_last<'a>(
-    pdfium: &'a Pdfium,
-    pdf: &PdfDocument<'a>,
-) -> Result<PdfDocument<'a>, PdfiumError> {
-    assert!(pdf.pages().len() > 1);
-
-    let mut new_doc = pdfium.create_new_pdf()?;
-    let mut front_index = 0;
-    let mut back_index = pdf.pages().len() - 1;
-    loop {
-        let new_doc_pages = new_doc.pages_mut();
-
-        new_doc_pages.copy_page_from_document(&pdf, front_index, new_doc_pages.len())?;
-        front_index += 1;
-        if front_index > back_index {
-            break;
+// Returns the page each part starts on in the merged book
+pub fn merge_parts<M: MergedPages>(
+    merged: &mut M,
+    pdfs: Vec<PdfPart>,
+) -> Result<Vec<usize>, M::Error> {
+    let mut starts = Vec::with_capacity(pdfs.len());
+    for pdf in pdfs {
+        // Page numbers start at one, so the next page is odd when the count is even
+        if matches!(pdf, PdfPart::Spread(_)) && merged.page_count().is_multiple_of(2) {
+            merged.push_blank_pages(1)?;
         }
-
-        new_doc_pages.copy_page_from_document(&pdf, back_index, new_doc_pages.len())?;
-        back_index -= 1;
-        if front_index > back_index {
-            break;
+        starts.push(merged.page_count());
+        match pdf {
+            PdfPart::Generated(pdf) | PdfPart::Spread(pdf) => merged.push_pdf(pdf)?,
+            PdfPart::File { path, pages } => merged.push_file(&path, pages)?,
         }
     }
+    Ok(starts)
+}
+
+// Scale the page content to fit the page size, keeping the aspect ratio and centering it
+fn fit_page_to_size(page: &mut PdfPage, size: PdfPagePaperSize) -> Result<(), PdfiumError> {
+    let (width, height) = (size.width().value, size.height().value);
+    let (page_width, page_height) = (page.width().value, page.height().value);
+    if (width - page_width).abs() < 1.0 && (height - page_height).abs() < 1.0 {
+        return Ok(());
+    }
 
-    Ok(new_doc)
+    let scale = (width / page_width).min(height / page_height);
+    page.scale(scale, scale)?;
+    page.translate(
+        PdfPoints::new((width - page_width * scale) / 2.0),
+        PdfPoints::new((height - page_height * scale) / 2.0),
+    )?;
+    let rect = PdfRect::new_from_values(0.0, 0.0, height, width);
+    page.boundaries_mut().set_media(rect)?;
+    page.boundaries_mut().set_crop(rect)?;
+    Ok(())
 }
 
+fn append_file<'a>(
+    pdfium: &'a Pdfium,
+    doc: &mut PdfDocument<'a>,
+    path: &str,
+    pages: Option<Vec<PdfPageIndex>>,
+    page_size: PdfPagePaperSize,
+) -> Result<(), PdfiumError> {
+    let bytes = std::fs::read(path).map_err(PdfiumError::IoError)?;
+    let file = pdfium.load_pdf_from_byte_vec(bytes, None)?;
+    let pages = pages.unwrap_or_else(|| (0..file.pages().len()).collect());
+
+    let first_new_page = doc.pages().len();
+    for page in pages {
+        let doc_pages = doc.pages_mut();
+        doc_pages.copy_page_from_document(&file, page, doc_pages.len())?;
+    }
+    for page_index in first_new_page..doc.pages().len() {
+        fit_page_to_size(&mut doc.pages_mut().get(page_index)?, page_size)?;
+    }
+    Ok(())
+}
+
+struct PdfiumPages<'a> {
+    pdfium: &'a Pdfium,
+    doc: PdfDocument<'a>,
+    page_size: PdfPagePaperSize,
+}
+
+impl<'a> MergedPages for PdfiumPages<'a> {
+    type Error = PdfiumError;
+
+    fn page_count(&self) -> usize {
+        self.doc.pages().len() as usize
+    }
+
+    fn push_blank_pages(&mut self, count: usize) -> Result<(), PdfiumError> {
+        add_blank_pages(&mut self.doc, count as PdfPageIndex, self.page_size)
+    }
+
+    fn push_pdf(&mut self, pdf: Vec<u8>) -> Result<(), PdfiumError> {
+        let pdf = self.pdfium.load_pdf_from_byte_vec(pdf, None)?;
+        self.doc.pages_mut().append(&pdf)
+    }
+
+    fn push_file(
+        &mut self,
+        path: &str,
+        pages: Option<Vec<PdfPageIndex>>,
+    ) -> Result<(), PdfiumError> {
+        append_file(self.pdfium, &mut self.doc, path, pages, self.page_size)
+    }
+}
+
+// Also returns the page each part starts on in the merged document
+pub fn merge_pdfs<'a>(
+    pdfium: &'a Pdfium,
+    mut pdfs: Vec<PdfPart>,
+    page_size: PdfPagePaperSize,
+) -> Result<(PdfDocument<'a>, Vec<usize>), PdfiumError> {
+    if pdfs.is_empty() {
+        return Ok((pdfium.create_new_pdf()?, Vec::new()));
+    } else if let [PdfPart::Generated(pdf)] = pdfs.as_mut_slice() {
+        return Ok((
+            pdfium.load_pdf_from_byte_vec(std::mem::take(pdf), None)?,
+            vec![0],
+        ));
+    }
+
+    let mut merged = PdfiumPages {
+        pdfium,
+        doc: pdfium.create_new_pdf()?,
+        page_size,
+    };
+    let starts = merge_parts(&mut merged, pdfs)?;
+    Ok((merged.doc, starts))
+}
+
+pub fn add_blank_pages(
+    doc: &mut PdfDocument,
+    count: PdfPageIndex,
+    page_size: PdfPagePaperSize,
+) -> Result<(), PdfiumError> {
+    for _ in 0..count {
+        doc.pages_mut().create_page_at_end(page_size)?;
+    }
+    Ok(())
+}
+
+// The size of the book's pages
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum PageSize {
     A4,
     A5,
     A6,
     A7,
+    HalfLetter,
+    Custom { width_mm: u16, height_mm: u16 },
+}
+
+impl PageSize {
+    pub fn size_mm(&self) -> (f64, f64) {
+        match self {
+            Self::A4 => (210.0, 297.0),
+            Self::A5 => (148.0, 210.0),
+            Self::A6 => (105.0, 148.0),
+            Self::A7 => (74.0, 105.0),
+            Self::HalfLetter => (139.7, 215.9),
+            Self::Custom {
+                width_mm,
+                height_mm,
+            } => (*width_mm as f64, *height_mm as f64),
+        }
+    }
 }
 
 impl fmt::Display for PageSize {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self {
-                Self::A4 => "Full Síða",
-                Self::A5 => "Hálf Síða",
-                Self::A6 => "1/4 Síða",
-                Self::A7 => "1/8 Síða",
-            }
-        )
+        let (width, height) = self.size_mm();
+        match self {
+            Self::A4 => write!(f, "A4")?,
+            Self::A5 => write!(f, "A5")?,
+            Self::A6 => write!(f, "A6")?,
+            Self::A7 => write!(f, "A7")?,
+            Self::HalfLetter => write!(f, "Half Letter")?,
+            Self::Custom { .. } => return write!(f, "{:.0} × {:.0} mm", width, height),
+        }
+        write!(f, " ({:.0} × {:.0} mm)", width, height)
     }
 }
 
-pub static PAGE_SIZE_VARIANTS: &[PageSize] =
-    &[PageSize::A4, PageSize::A5, PageSize::A6, PageSize::A7];
+pub static PAGE_SIZE_VARIANTS: &[PageSize] = &[
+    PageSize::A4,
+    PageSize::A5,
+    PageSize::A6,
+    PageSize::A7,
+    PageSize::HalfLetter,
+];
 
-pub fn tile_pages<'a>(
-    pages: &'a PdfPages,
-    scaling_factor: f32,
-    add_separator: bool,
-    tiling: PageSize,
-) -> Result<PdfDocument<'a>, PdfiumError> {
-    let movement_to_center = (1.0 - scaling_factor) / 2.0;
-    let (rows_per_page, columns_per_page, page_size) = match tiling {
-        PageSize::A4 => (1, 1, PdfPagePaperSize::a4().portrait()),
-        PageSize::A5 => (1, 2, PdfPagePaperSize::a4().landscape()),
-        PageSize::A6 => (2, 2, PdfPagePaperSize::a4().portrait()),
-        PageSize::A7 => (2, 4, PdfPagePaperSize::a4().landscape()),
-    };
-    let mut tiled_doc = pages.tile_into_new_document(rows_per_page, columns_per_page, page_size)?;
-
-    for page_num in 0..tiled_doc.pages().len() {
-        let mut page = tiled_doc.pages_mut().get(page_num)?;
-        let (page_width, page_height) = (page.width(), page.height());
-
-        // Add the page separators if requested
-        if add_separator {
-            let objects = page.objects_mut();
-            let separator_color = PdfColor::new(0, 0, 0, 255);
-            for i in 0..(columns_per_page + 1) {
-                let width = page_width * (i as f32 / columns_per_page as f32);
-                objects.create_path_object_line(
-                    width,
-                    PdfPoints::ZERO,
-                    width,
-                    page_height,
-                    separator_color,
-                    PdfPoints::new(1.0),
-                )?;
-            }
-            for i in 0..(rows_per_page + 1) {
-                let height = page_height * (i as f32 / rows_per_page as f32);
-                objects.create_path_object_line(
-                    PdfPoints::ZERO,
-                    height,
-                    page_width,
-                    height,
-                    separator_color,
-                    PdfPoints::new(1.0),
-                )?;
-            }
+// The size of the sheets the pages are printed on, in portrait
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PaperSize {
+    A3,
+    A4,
+    Letter,
+    Legal,
+    Custom { width_mm: u16, height_mm: u16 },
+}
+
+impl PaperSize {
+    pub fn size_mm(&self) -> (f64, f64) {
+        match self {
+            Self::A3 => (297.0, 420.0),
+            Self::A4 => (210.0, 297.0),
+            Self::Letter => (215.9, 279.4),
+            Self::Legal => (215.9, 355.6),
+            Self::Custom {
+                width_mm,
+                height_mm,
+            } => (*width_mm as f64, *height_mm as f64),
+        }
+    }
+}
+
+impl fmt::Display for PaperSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (width, height) = self.size_mm();
+        match self {
+            Self::A3 => write!(f, "A3")?,
+            Self::A4 => write!(f, "A4")?,
+            Self::Letter => write!(f, "Letter")?,
+            Self::Legal => write!(f, "Legal")?,
+            Self::Custom { .. } => return write!(f, "{:.0} × {:.0} mm", width, height),
         }
+        write!(f, " ({:.0} × {:.0} mm)", width, height)
+    }
+}
+
+pub static PAPER_SIZE_VARIANTS: &[PaperSize] = &[
+    PaperSize::A3,
+    PaperSize::A4,
+    PaperSize::Letter,
+    PaperSize::Legal,
+];
+
+pub fn paper_size((width, height): (f64, f64)) -> PdfPagePaperSize {
+    PdfPagePaperSize::Custom(mm(width), mm(height))
+}
 
-        // Scale each page to add margin for printing
-        page.scale(scaling_factor, scaling_factor)?;
-        page.translate(
-            page.width() * movement_to_center,
-            page.height() * movement_to_center,
+fn mm(value: f64) -> PdfPoints {
+    PdfPoints::from_mm(value as f32)
+}
+
+fn draw_lines(page: &mut PdfPage, lines: &[Line], thickness: f32) -> Result<(), PdfiumError> {
+    for line in lines {
+        page.objects_mut().create_path_object_line(
+            mm(line.from.0),
+            mm(line.from.1),
+            mm(line.to.0),
+            mm(line.to.1),
+            PdfColor::new(0, 0, 0, 255),
+            PdfPoints::new(thickness),
         )?;
     }
+    Ok(())
+}
 
-    return Ok(tiled_doc);
+struct PdfiumCanvas<'a, 'b> {
+    source: &'b PdfDocument<'a>,
+    doc: PdfDocument<'a>,
+    page: Option<PdfPage<'a>>,
+    sheet_size: (f64, f64),
+    font: PdfFontToken,
+}
+
+impl<'a, 'b> PdfiumCanvas<'a, 'b> {
+    fn page(&mut self) -> &mut PdfPage<'a> {
+        self.page
+            .as_mut()
+            .expect("Each side is started before anything is drawn on it")
+    }
+}
+
+impl<'a, 'b> SheetCanvas for PdfiumCanvas<'a, 'b> {
+    type Error = PdfiumError;
+
+    fn start_side(&mut self) -> Result<(), PdfiumError> {
+        let size = paper_size(self.sheet_size);
+        self.page = Some(self.doc.pages_mut().create_page_at_end(size)?);
+        Ok(())
+    }
+
+    fn draw_page(
+        &mut self,
+        index: usize,
+        position: (f64, f64),
+        scale: f64,
+    ) -> Result<(), PdfiumError> {
+        let source = self.source.pages().get(index as PdfPageIndex)?;
+        let mut object = source
+            .objects()
+            .copy_into_x_object_form_object(&mut self.doc)?;
+        object.scale(scale as f32, scale as f32)?;
+        object.translate(mm(position.0), mm(position.1))?;
+        self.page().objects_mut().add_object(object)?;
+        Ok(())
+    }
+
+    fn draw_lines(&mut self, lines: &[Line], thickness: f32) -> Result<(), PdfiumError> {
+        draw_lines(self.page(), lines, thickness)
+    }
+
+    fn fill_rect(&mut self, rect: Rect) -> Result<(), PdfiumError> {
+        self.page().objects_mut().create_path_object_rect(
+            PdfRect::new(mm(rect.y), mm(rect.x), mm(rect.top()), mm(rect.right())),
+            None,
+            None,
+            Some(PdfColor::new(0, 0, 0, 255)),
+        )?;
+        Ok(())
+    }
+
+    fn draw_text(&mut self, position: (f64, f64), text: &str) -> Result<(), PdfiumError> {
+        let font = self.font;
+        self.page().objects_mut().create_text_object(
+            mm(position.0),
+            mm(position.1),
+            text,
+            font,
+            PdfPoints::new(7.0),
+        )?;
+        Ok(())
+    }
+
+    fn finish_side(&mut self, rotated: bool) -> Result<(), PdfiumError> {
+        if let Some(mut page) = self.page.take() {
+            if rotated {
+                page.set_rotation(PdfPageRenderRotation::Degrees180);
+            }
+        }
+        Ok(())
+    }
+}
+
+// The sides of the sheets as a new document
+pub fn tile_pages<'a>(
+    pdfium: &'a Pdfium,
+    doc: &PdfDocument<'a>,
+    sides: &[SheetSide],
+    sheet_size: (f64, f64),
+    copies: &[SheetGeometry],
+    add_separator: bool,
+    title: &str,
+) -> Result<PdfDocument<'a>, PdfiumError> {
+    let mut tiled_doc = pdfium.create_new_pdf()?;
+    let font = tiled_doc.fonts_mut().helvetica();
+    let mut canvas = PdfiumCanvas {
+        source: doc,
+        doc: tiled_doc,
+        page: None,
+        sheet_size,
+        font,
+    };
+    sheet::draw_sides(&mut canvas, sides, copies, add_separator, title)?;
+    Ok(canvas.doc)
 }
@@ -1,7 +1,7 @@
 use std::{fs, io};
 
 use eframe::egui;
-use generator::config::Song;
+use generator::config::{Section, Song, SongListItem};
 
 fn get_available_song(res: io::Result<fs::DirEntry>) -> Result<Song, String> {
     let path = res
@@ -17,7 +17,7 @@ fn get_available_song(res: io::Result<fs::DirEntry>) -> Result<Song, String> {
 
 fn get_available_songs() -> Vec<Song> {
     let mut songs = Vec::new();
-    for res in fs::read_dir("./songs").unwrap() {
+    for res in fs::read_dir(generator::SONGS_DIR).unwrap() {
         match get_available_song(res) {
             Ok(song) => songs.push(song),
             Err(err) => {
@@ -40,16 +40,22 @@ impl AddSong {
         self.open = true;
         self.available_songs = get_available_songs();
     }
-    pub fn ui(&mut self, ui: &mut egui::Ui) -> Option<Song> {
+    pub fn ui(&mut self, ui: &mut egui::Ui) -> Option<SongListItem> {
         let a = egui::Window::new("Bæta við lagi")
             .vscroll(true)
             .collapsible(false)
             .open(&mut self.open)
             .show(ui.ctx(), |ui| {
                 let mut selected = None;
+                if ui.button("Nýr kafli").clicked() {
+                    selected = Some(SongListItem::Section {
+                        section: Section::default(),
+                    });
+                }
+                ui.separator();
                 for song in &self.available_songs {
                     if ui.button(&song.title).clicked() {
-                        selected = Some(song.clone());
+                        selected = Some(SongListItem::Song(song.clone()));
                     }
                 }
                 selected
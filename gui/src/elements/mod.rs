@@ -6,3 +6,6 @@ pub use select_font::SelectFont;
 
 mod select_tile_page_size;
 pub use select_tile_page_size::SelectTilePageSize;
+
+mod select_sheet_size;
+pub use select_sheet_size::SelectSheetSize;
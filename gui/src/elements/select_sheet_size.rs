@@ -0,0 +1,43 @@
+use eframe::egui;
+use generator::tile;
+
+#[derive(Debug, Default)]
+pub struct SelectSheetSize {}
+
+impl SelectSheetSize {
+    pub fn ui(&mut self, ui: &mut egui::Ui, selected: &mut tile::PaperSize) -> egui::Response {
+        let mut response = egui::ComboBox::from_label("")
+            .selected_text(format!("{}", selected))
+            .show_ui(ui, |ui| {
+                for size in tile::PAPER_SIZE_VARIANTS {
+                    ui.selectable_value(selected, *size, format!("{}", *size));
+                }
+                if !matches!(selected, tile::PaperSize::Custom { .. }) {
+                    let (width, height) = selected.size_mm();
+                    ui.selectable_value(
+                        selected,
+                        tile::PaperSize::Custom {
+                            width_mm: width.round() as u16,
+                            height_mm: height.round() as u16,
+                        },
+                        "Sérstærð",
+                    );
+                }
+            })
+            .response;
+
+        if let tile::PaperSize::Custom {
+            width_mm,
+            height_mm,
+        } = selected
+        {
+            ui.horizontal(|ui| {
+                response |= ui.add(egui::DragValue::new(width_mm).clamp_range(20..=1000));
+                ui.label("×");
+                response |= ui.add(egui::DragValue::new(height_mm).clamp_range(20..=1000));
+                ui.label("mm");
+            });
+        }
+        response
+    }
+}
@@ -6,13 +6,38 @@ pub struct SelectTilePageSize {}
 
 impl SelectTilePageSize {
     pub fn ui(&mut self, ui: &mut egui::Ui, selected: &mut tile::PageSize) -> egui::Response {
-        egui::ComboBox::from_label("")
+        let mut response = egui::ComboBox::from_label("")
             .selected_text(format!("{}", selected))
             .show_ui(ui, |ui| {
                 for font in tile::PAGE_SIZE_VARIANTS {
                     ui.selectable_value(selected, *font, format!("{}", *font));
                 }
+                if !matches!(selected, tile::PageSize::Custom { .. }) {
+                    let (width, height) = selected.size_mm();
+                    ui.selectable_value(
+                        selected,
+                        tile::PageSize::Custom {
+                            width_mm: width.round() as u16,
+                            height_mm: height.round() as u16,
+                        },
+                        "Sérstærð",
+                    );
+                }
             })
-            .response
+            .response;
+
+        if let tile::PageSize::Custom {
+            width_mm,
+            height_mm,
+        } = selected
+        {
+            ui.horizontal(|ui| {
+                response |= ui.add(egui::DragValue::new(width_mm).clamp_range(20..=1000));
+                ui.label("×");
+                response |= ui.add(egui::DragValue::new(height_mm).clamp_range(20..=1000));
+                ui.label("mm");
+            });
+        }
+        response
     }
 }
@@ -1,6 +1,10 @@
-use std::fs;
+use std::sync::mpsc;
+use std::{fs, thread};
 
-use generator::config::BookConfig;
+use eframe::egui;
+use generator::config::{BookConfig, SongListItem};
+use generator::progress::{CancelToken, Progress, ProgressReporter};
+use generator::GenerationError;
 
 const DEFAULT_FONT: &str = "Arial";
 
@@ -14,7 +18,7 @@ pub fn font_exists(font: &str) -> bool {
 pub fn load_book() -> BookConfig {
     // Load settings from file
     let mut book = match fs::read_to_string("settings.json") {
-        Ok(c) => match serde_json::from_str::<BookConfig>(&c) {
+        Ok(c) => match BookConfig::from_json(&c) {
             Ok(b) => b,
             Err(_) => {
                 fs::rename("settings.json", "settings.old.json").unwrap();
@@ -38,15 +42,22 @@ pub fn load_book() -> BookConfig {
     book.songs = book
         .songs
         .into_iter()
-        .filter_map(|s| match generator::load_song(&s.title) {
-            Ok(s) => Some(s),
-            Err(e) => {
-                println!(
-                    "Failed to find song \"{}\": {}. Removing it from the current songbook",
-                    s.title, e
-                );
-                None
-            }
+        .filter_map(|item| match item {
+            SongListItem::Section { .. } => Some(item),
+            SongListItem::Song(s) => match generator::load_song(&s.title) {
+                Ok(mut loaded) => {
+                    // Keep the column count chosen in the program over the one in the song file
+                    loaded.columns = s.columns.or(loaded.columns);
+                    Some(SongListItem::Song(loaded))
+                }
+                Err(e) => {
+                    println!(
+                        "Failed to find song \"{}\": {}. Removing it from the current songbook",
+                        s.title, e
+                    );
+                    None
+                }
+            },
         })
         .collect();
 
@@ -61,16 +72,14 @@ pub fn load_book() -> BookConfig {
     book
 }
 
-pub fn generate_pdf(book: &BookConfig) {
-    // Make sure we have some pages to generate
-    if book.songs.is_empty() && book.front_pages.is_empty() && book.back_pages.is_empty() {
-        println!("No songs or pages to generate!");
-        return;
-    }
-
+fn generate_pdf(book: &BookConfig, progress: &ProgressReporter) {
     // Generate the songbook PDF
-    let pdf = match generator::generate_book_pdf(&book) {
+    let pdf = match generator::generate_book_pdf_with_progress(book, progress) {
         Ok(pdf) => pdf,
+        Err(GenerationError::Cancelled) => {
+            println!("PDF generation cancelled");
+            return;
+        }
         Err(e) => {
             println!("Error generating PDF: {}", e);
             return;
@@ -89,3 +98,57 @@ pub fn generate_pdf(book: &BookConfig) {
         Err(e) => println!("Error opening PDF: {}", e),
     };
 }
+
+// A book being generated on its own thread, so the window keeps responding
+#[derive(Debug)]
+pub struct Generation {
+    progress: mpsc::Receiver<Progress>,
+    cancel: CancelToken,
+    thread: thread::JoinHandle<()>,
+    pub last_progress: Option<Progress>,
+}
+
+impl Generation {
+    pub fn start(book: &BookConfig, ctx: &egui::Context) -> Option<Generation> {
+        // Make sure we have some pages to generate
+        if book.songs.is_empty() && book.front_pages.is_empty() && book.back_pages.is_empty() {
+            println!("No songs or pages to generate!");
+            return None;
+        }
+
+        let (sender, receiver) = mpsc::channel();
+        let cancel = CancelToken::new();
+        let thread = {
+            let book = book.clone();
+            let ctx = ctx.clone();
+            let cancel = cancel.clone();
+            thread::spawn(move || {
+                let on_progress = |progress| {
+                    // The window may have been closed, and then nobody needs to know
+                    let _ = sender.send(progress);
+                    ctx.request_repaint();
+                };
+                generate_pdf(&book, &ProgressReporter::new(&on_progress, cancel));
+                ctx.request_repaint();
+            })
+        };
+        Some(Generation {
+            progress: receiver,
+            cancel,
+            thread,
+            last_progress: None,
+        })
+    }
+
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    // Takes in the progress reported since the last frame, and returns whether it has finished
+    pub fn update(&mut self) -> bool {
+        while let Ok(progress) = self.progress.try_recv() {
+            self.last_progress = Some(progress);
+        }
+        self.thread.is_finished()
+    }
+}
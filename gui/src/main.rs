@@ -3,8 +3,12 @@ use serde_json;
 use std::fs;
 
 use generator::config::{
-    BookConfig, FrontPage, Page, Preface, TableOfContents, TableOfContentsSortOrder,
+    AuthorIndex, BookConfig, CustomPage, ExternalPdf, FrontPage, ImageAlignment, ImageConfig,
+    Notes, Page, PdfBackend, Preface, Section, SectionStyle, Song, SongListItem, SongNumbering,
+    TableOfContents, TableOfContentsSortOrder,
 };
+use generator::imposition::{Imposition, DUPLEX_FLIP_VARIANTS, IMPOSITION_VARIANTS};
+use generator::sheet::GridSize;
 
 use eframe::egui;
 
@@ -37,6 +41,8 @@ struct State {
     add_page: Option<PageLocation>,
     select_font: elements::SelectFont,
     select_tile_page_size: elements::SelectTilePageSize,
+    select_sheet_size: elements::SelectSheetSize,
+    generation: Option<helpers::Generation>,
 }
 
 impl State {
@@ -55,7 +61,16 @@ impl State {
                 ItemListConfig {
                     label: "Lög",
                     items: &mut self.book.songs,
-                    render_item: |ui, _, song| ui.label(&song.title),
+                    render_item: |ui, i, item| match item {
+                        SongListItem::Song(song) => {
+                            ui.push_id(format!("l_{}", i), |ui| view_song(ui, song))
+                                .response
+                        }
+                        SongListItem::Section { section } => {
+                            ui.push_id(format!("s_{}", i), |ui| view_section(ui, section))
+                                .response
+                        }
+                    },
                     on_add: || self.add_song.open(),
                 },
             )
@@ -87,8 +102,8 @@ impl State {
             )
             .write(self);
 
-            if let Some(song) = self.add_song.ui(ui) {
-                self.book.songs.push(song);
+            if let Some(item) = self.add_song.ui(ui) {
+                self.book.songs.push(item);
                 self.write_settings();
             }
             if let Some(location) = self.add_page {
@@ -108,6 +123,11 @@ impl State {
                             "Efnisyfirlit",
                             Page::TableOfContents(TableOfContents::default()),
                         ),
+                        ("Höfundaskrá", Page::AuthorIndex(AuthorIndex::default())),
+                        ("Eigin síða", Page::Custom(CustomPage::default())),
+                        ("PDF skjal", Page::ExternalPdf(ExternalPdf::default())),
+                        ("Auð síða", Page::Blank),
+                        ("Glósusíða", Page::Notes(Notes::default())),
                     ];
                     for (label, page) in options {
                         if ui.button(label).clicked() {
@@ -156,6 +176,73 @@ impl eframe::App for State {
                                     .write(self);
                             });
                             ui.end_row();
+
+                            ui.label("Örk");
+                            ui.push_id("sheet_size", |ui| {
+                                self.select_sheet_size
+                                    .ui(ui, &mut self.book.sheet_size)
+                                    .write(self);
+                            });
+                            ui.end_row();
+
+                            ui.label("Síður á örk");
+                            ui.horizontal(|ui| {
+                                let mut automatic = self.book.grid.is_none();
+                                if ui.checkbox(&mut automatic, "Sjálfvirkt").changed() {
+                                    self.book.grid = if automatic {
+                                        None
+                                    } else {
+                                        Some(GridSize {
+                                            rows: 2,
+                                            columns: 2,
+                                        })
+                                    };
+                                    self.write_settings();
+                                }
+                                if let Some(grid) = &mut self.book.grid {
+                                    let rows = ui.add(
+                                        egui::DragValue::new(&mut grid.rows).clamp_range(1..=8),
+                                    );
+                                    ui.label("×");
+                                    let columns = ui.add(
+                                        egui::DragValue::new(&mut grid.columns).clamp_range(1..=8),
+                                    );
+                                    (rows | columns).write(self);
+                                }
+                            });
+                            ui.end_row();
+
+                            ui.label("Fyrirsagnir (pt)");
+                            ui.add(egui::Slider::new(
+                                &mut self.book.typography.title_font_size,
+                                6..=48,
+                            ))
+                            .write(self);
+                            ui.end_row();
+
+                            ui.label("Titlar laga (pt)");
+                            ui.add(egui::Slider::new(
+                                &mut self.book.typography.song_title_font_size,
+                                6..=48,
+                            ))
+                            .write(self);
+                            ui.end_row();
+
+                            ui.label("Meginmál (pt)");
+                            ui.add(egui::Slider::new(
+                                &mut self.book.typography.body_font_size,
+                                6..=36,
+                            ))
+                            .write(self);
+                            ui.end_row();
+
+                            ui.label("Spássíur (mm)");
+                            ui.add(egui::Slider::new(
+                                &mut self.book.typography.margins_mm,
+                                0..=30,
+                            ))
+                            .write(self);
+                            ui.end_row();
                         });
                     });
                     ui.vertical(|ui| {
@@ -164,16 +251,198 @@ impl eframe::App for State {
                             ui.checkbox(&mut self.book.add_separator, "").write(self);
                             ui.end_row();
 
-                            ui.label("Endurraða síðum");
-                            ui.checkbox(&mut self.book.reorder_pages, "").write(self);
+                            ui.label("Eintök á örk");
+                            ui.add(egui::Slider::new(&mut self.book.copies_per_sheet, 1..=8))
+                                .write(self);
+                            ui.end_row();
+
+                            ui.label("Skurðarmerki");
+                            ui.checkbox(&mut self.book.crop_marks, "").write(self);
+                            ui.end_row();
+
+                            ui.label("Blæðing (mm)");
+                            ui.add(egui::Slider::new(&mut self.book.bleed_mm, 0..=10))
+                                .write(self);
+                            ui.end_row();
+
+                            ui.label("Umbrot");
+                            egui::ComboBox::from_id_source("imposition")
+                                .selected_text(format!("{}", self.book.imposition))
+                                .show_ui(ui, |ui| {
+                                    for imposition in IMPOSITION_VARIANTS {
+                                        ui.selectable_value(
+                                            &mut self.book.imposition,
+                                            *imposition,
+                                            format!("{}", imposition),
+                                        );
+                                    }
+                                })
+                                .response
+                                .write(self);
+                            ui.end_row();
+
+                            ui.label("Blöð í örk");
+                            ui.add_enabled(
+                                self.book.imposition == Imposition::Signatures,
+                                egui::Slider::new(&mut self.book.sheets_per_signature, 1..=16),
+                            )
+                            .write(self);
+                            ui.end_row();
+
+                            ui.label("Tilfærsla að kili (µm á blað)");
+                            ui.add_enabled(
+                                self.book.imposition.is_folded(),
+                                egui::Slider::new(&mut self.book.creep_per_leaf_um, 0..=300),
+                            )
+                            .write(self);
+                            ui.end_row();
+
+                            ui.label("Snúa við á");
+                            egui::ComboBox::from_id_source("duplex_flip")
+                                .selected_text(format!("{}", self.book.duplex_flip))
+                                .show_ui(ui, |ui| {
+                                    for flip in DUPLEX_FLIP_VARIANTS {
+                                        ui.selectable_value(
+                                            &mut self.book.duplex_flip,
+                                            *flip,
+                                            format!("{}", flip),
+                                        );
+                                    }
+                                })
+                                .response
+                                .write(self);
+                            ui.end_row();
+
+                            ui.label("Fylla upp í arkir");
+                            ui.checkbox(&mut self.book.pad_to_full_sheets, "")
+                                .write(self);
+                            ui.end_row();
+
+                            ui.label("Halda erindum saman");
+                            ui.checkbox(&mut self.book.song_layout.keep_verses_together, "")
+                                .write(self);
                             ui.end_row();
-                        });
 
-                        ui.centered_and_justified(|ui| {
-                            if ui.button("Búa til PDF").clicked() {
-                                helpers::generate_pdf(&self.book);
+                            ui.label("Titill með fyrsta erindi");
+                            ui.checkbox(&mut self.book.song_layout.keep_title_with_verse, "")
+                                .write(self);
+                            ui.end_row();
+
+                            ui.label("Hvert lag á nýrri síðu");
+                            ui.checkbox(&mut self.book.song_layout.new_page_per_song, "")
+                                .write(self);
+                            ui.end_row();
+
+                            ui.label("Löng lög á opnu");
+                            ui.checkbox(&mut self.book.song_layout.long_songs_on_even_page, "")
+                                .write(self);
+                            ui.end_row();
+
+                            ui.label("Hvert lag á eina síðu");
+                            ui.horizontal(|ui| {
+                                ui.checkbox(&mut self.book.song_layout.fit_to_page, "")
+                                    .write(self);
+                                ui.add_enabled(
+                                    self.book.song_layout.fit_to_page,
+                                    egui::Slider::new(
+                                        &mut self.book.song_layout.min_font_size,
+                                        6..=24,
+                                    )
+                                    .text("Minnsta letur"),
+                                )
+                                .write(self);
+                            });
+                            ui.end_row();
+
+                            ui.label("Dálkar");
+                            ui.add(egui::Slider::new(&mut self.book.columns, 1..=4))
+                                .write(self);
+                            ui.end_row();
+
+                            ui.label("Númerun laga");
+                            egui::ComboBox::from_id_source("song_numbering")
+                                .selected_text(format!("{}", self.book.song_numbering))
+                                .show_ui(ui, |ui| {
+                                    let numberings =
+                                        [SongNumbering::Continuous, SongNumbering::PerSection];
+                                    for numbering in numberings {
+                                        ui.selectable_value(
+                                            &mut self.book.song_numbering,
+                                            numbering,
+                                            format!("{}", numbering),
+                                        );
+                                    }
+                                })
+                                .response
+                                .write(self);
+                            ui.end_row();
+
+                            ui.label("PDF vinnsla");
+                            egui::ComboBox::from_id_source("pdf_backend")
+                                .selected_text(format!("{}", self.book.pdf_backend))
+                                .show_ui(ui, |ui| {
+                                    for backend in [PdfBackend::Native, PdfBackend::Pdfium] {
+                                        ui.selectable_value(
+                                            &mut self.book.pdf_backend,
+                                            backend,
+                                            format!("{}", backend),
+                                        );
+                                    }
+                                })
+                                .response
+                                .write(self);
+                            ui.end_row();
+
+                            ui.label("Slóð á Pdfium");
+                            let mut pdfium_path = self.book.pdfium_path.clone().unwrap_or_default();
+                            let response = ui.add_enabled(
+                                self.book.pdf_backend == PdfBackend::Pdfium,
+                                egui::TextEdit::singleline(&mut pdfium_path),
+                            );
+                            if response.changed() {
+                                self.book.pdfium_path =
+                                    (!pdfium_path.is_empty()).then_some(pdfium_path);
                             }
+                            response.write(self);
+                            ui.end_row();
                         });
+
+                        if self
+                            .generation
+                            .as_mut()
+                            .is_some_and(|generation| generation.update())
+                        {
+                            self.generation = None;
+                        }
+                        match &self.generation {
+                            Some(generation) => {
+                                ui.horizontal(|ui| {
+                                    if ui.button("Hætta við").clicked() {
+                                        generation.cancel();
+                                    }
+                                    let progress = generation.last_progress;
+                                    ui.add(
+                                        egui::ProgressBar::new(
+                                            progress.map_or(0.0, |progress| progress.fraction()),
+                                        )
+                                        .text(
+                                            progress.map_or_else(
+                                                || "Býr til PDF".to_owned(),
+                                                |progress| progress.to_string(),
+                                            ),
+                                        ),
+                                    );
+                                });
+                            }
+                            None => {
+                                ui.centered_and_justified(|ui| {
+                                    if ui.button("Búa til PDF").clicked() {
+                                        self.generation =
+                                            helpers::Generation::start(&self.book, ctx);
+                                    }
+                                });
+                            }
+                        }
                     });
                 });
             });
@@ -188,6 +457,84 @@ impl eframe::App for State {
     }
 }
 
+fn view_section(ui: &mut egui::Ui, section: &mut Section) -> egui::Response {
+    ui.vertical(|ui| {
+        ui.set_min_size(egui::vec2(200.0, 4.0));
+        ui.label("Kafli");
+        ui.text_edit_singleline(&mut section.title);
+        ui.text_edit_multiline(&mut section.description);
+        ui.horizontal(|ui| {
+            ui.label("Forskeyti");
+            ui.text_edit_singleline(&mut section.prefix);
+        });
+        egui::ComboBox::from_label("Útlit")
+            .selected_text(format!("{}", section.style))
+            .show_ui(ui, |ui| {
+                for style in [SectionStyle::Heading, SectionStyle::FullPage] {
+                    ui.selectable_value(&mut section.style, style, format!("{}", style));
+                }
+            });
+    })
+    .response
+}
+
+fn view_images(ui: &mut egui::Ui, images: &mut Vec<ImageConfig>) {
+    let mut removed = None;
+    for (i, image) in images.iter_mut().enumerate() {
+        ui.push_id(format!("image_{}", i), |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Mynd");
+                ui.text_edit_singleline(&mut image.path);
+                if ui.button("Eyða").clicked() {
+                    removed = Some(i);
+                }
+            });
+            ui.add(egui::Slider::new(&mut image.width, 1..=100).text("Breidd %"));
+            egui::ComboBox::from_label("Staðsetning")
+                .selected_text(format!("{}", image.alignment))
+                .show_ui(ui, |ui| {
+                    let alignments = [
+                        ImageAlignment::Left,
+                        ImageAlignment::Center,
+                        ImageAlignment::Right,
+                    ];
+                    for alignment in alignments {
+                        ui.selectable_value(
+                            &mut image.alignment,
+                            alignment,
+                            format!("{}", alignment),
+                        );
+                    }
+                });
+        });
+    }
+    if let Some(i) = removed {
+        images.remove(i);
+    }
+    if ui.button("Bæta við mynd").clicked() {
+        images.push(ImageConfig::default());
+    }
+}
+
+fn view_song(ui: &mut egui::Ui, song: &mut Song) -> egui::Response {
+    ui.horizontal(|ui| {
+        ui.label(&song.title);
+        let column_text = |columns: Option<u8>| match columns {
+            Some(1) => "1 dálkur".to_owned(),
+            Some(columns) => format!("{} dálkar", columns),
+            None => "Sjálfgefið".to_owned(),
+        };
+        egui::ComboBox::from_id_source("columns")
+            .selected_text(column_text(song.columns))
+            .show_ui(ui, |ui| {
+                for columns in [None, Some(1), Some(2), Some(3)] {
+                    ui.selectable_value(&mut song.columns, columns, column_text(columns));
+                }
+            });
+    })
+    .response
+}
+
 fn view_page(ui: &mut egui::Ui, page: &mut generator::config::Page) -> egui::Response {
     ui.vertical(|ui| {
         ui.set_min_size(egui::vec2(200.0, 4.0));
@@ -196,6 +543,7 @@ fn view_page(ui: &mut egui::Ui, page: &mut generator::config::Page) -> egui::Res
                 ui.label("Formáli");
                 ui.text_edit_singleline(&mut p.title);
                 ui.text_edit_multiline(&mut p.body);
+                ui.checkbox(&mut p.markdown, "Markdown");
             }
             generator::config::Page::TableOfContents(p) => {
                 ui.label("Efnisyfirlit");
@@ -211,11 +559,38 @@ fn view_page(ui: &mut egui::Ui, page: &mut generator::config::Page) -> egui::Res
                             ui.selectable_value(&mut p.order, order, format!("{}", order));
                         }
                     });
+                ui.add(egui::Slider::new(&mut p.columns, 1..=4).text("Dálkar"));
             }
             generator::config::Page::FrontPage(p) => {
                 ui.label("Forsíða");
                 ui.text_edit_singleline(&mut p.title);
                 ui.text_edit_singleline(&mut p.version);
+                view_images(ui, &mut p.images);
+            }
+            generator::config::Page::AuthorIndex(p) => {
+                ui.label("Höfundaskrá");
+                ui.text_edit_singleline(&mut p.title);
+            }
+            generator::config::Page::Custom(p) => {
+                ui.label("Eigin síða (Markdown)");
+                ui.text_edit_singleline(&mut p.title);
+                ui.code_editor(&mut p.body);
+            }
+            generator::config::Page::Blank => {
+                ui.label("Auð síða");
+            }
+            generator::config::Page::Notes(p) => {
+                ui.label("Glósusíða");
+                ui.text_edit_singleline(&mut p.title);
+                ui.add(egui::Slider::new(&mut p.lines, 0..=100).text("Línur"));
+            }
+            generator::config::Page::ExternalPdf(p) => {
+                ui.label("PDF skjal");
+                ui.text_edit_singleline(&mut p.path);
+                ui.horizontal(|ui| {
+                    ui.label("Síður");
+                    ui.text_edit_singleline(&mut p.pages);
+                });
             }
         }
     })